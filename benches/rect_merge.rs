@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+use bevy_ecs_ldtk::prelude::GridCoords;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// The game is a binary, so the module is pulled in directly.
+// Its unit tests are compiled out here, leaving their imports unused.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/rect_merge.rs"]
+mod rect_merge;
+
+use rect_merge::MergeMode;
+
+const SIZE: i32 = 512;
+
+/// Solid rock cut by vertical shafts, horizontal galleries and one diagonal staircase,
+/// roughly what the levels look like
+fn shafts() -> HashSet<GridCoords> {
+    grid(|x, y| {
+        let shaft = x % 32 < 3;
+        let gallery = y % 64 < 6;
+        let staircase = (x - y).rem_euclid(SIZE) < 8;
+        !(shaft || gallery || staircase)
+    })
+}
+
+/// Random 16x16 blocks of rock and water.
+/// Random single cells would be a worse case, but `Maximal` takes far too long on those.
+fn blocks() -> HashSet<GridCoords> {
+    const BLOCK: i32 = 16;
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let filled: Vec<bool> = (0..(SIZE / BLOCK).pow(2)).map(|_| rng.gen_bool(0.5)).collect();
+    grid(|x, y| filled[(y / BLOCK * (SIZE / BLOCK) + x / BLOCK) as usize])
+}
+
+fn grid(mut filled: impl FnMut(i32, i32) -> bool) -> HashSet<GridCoords> {
+    (0..SIZE)
+        .flat_map(|y| (0..SIZE).map(move |x| GridCoords { x, y }))
+        .filter(|cell| filled(cell.x, cell.y))
+        .collect()
+}
+
+fn merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("merge {SIZE}x{SIZE}"));
+    group.sample_size(10);
+
+    for (name, cells) in [("shafts", shafts()), ("blocks", blocks())] {
+        for mode in [MergeMode::Rows, MergeMode::Maximal] {
+            group.bench_with_input(BenchmarkId::new(format!("{mode:?}"), name), &cells, |b, cells| {
+                b.iter(|| mode.merge(cells, SIZE, SIZE))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, merge);
+criterion_main!(benches);
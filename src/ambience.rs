@@ -0,0 +1,360 @@
+use std::io::Cursor;
+use bevy::audio::{AddAudioSource, AudioSinkPlayback, Decodable, Source, Volume};
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy::reflect::{TypePath, TypeUuid};
+use bevy_xpbd_2d::prelude::*;
+use crate::game_state::{GameState, InGame};
+use crate::merged_collider::MergedRect;
+use crate::water::{depth_below_surface, InWater, WaterBody};
+use crate::Player;
+
+/// Looping background sound that follows the player in and out of the water.
+///
+/// Going under crossfades the open air loop into a low-passed copy of itself,
+/// along with a bubbling loop, both of which get louder with depth.
+/// Surfacing crossfades back. All mixing goes through the `AudioSink` volumes of the loops.
+pub struct AmbiencePlugin;
+
+impl Plugin for AmbiencePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_audio_source::<Muffled>()
+            .init_resource::<AmbienceSettings>()
+            .init_resource::<AmbienceClips>()
+            .add_systems(OnEnter(GameState::Playing), resume_ambience)
+            .add_systems(OnExit(GameState::Playing), pause_ambience)
+            .add_systems(Update, (spawn_ambience, crossfade_ambience::<AudioSink>).run_if(in_state(GameState::Playing)));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct AmbienceSettings {
+    pub surface_clip: &'static str,
+    pub underwater_clip: &'static str,
+    /// Loudest volume of each loop
+    pub surface_volume: f32,
+    pub underwater_volume: f32,
+    /// Highest frequency of the surface loop that makes it through the water, in hertz
+    pub muffled_cutoff: u32,
+    /// Depth below the surface at which the underwater loops are at their loudest
+    pub full_depth: f32,
+    /// How quickly the loops move towards their target volumes, per second
+    pub fade_rate: f32,
+}
+
+impl Default for AmbienceSettings {
+    fn default() -> Self {
+        Self {
+            surface_clip: "audio/creak-1.wav",
+            underwater_clip: "audio/bubbles-1.wav",
+            surface_volume: 0.5,
+            underwater_volume: 0.8,
+            muffled_cutoff: 400,
+            full_depth: 32.0,
+            fade_rate: 3.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Component)]
+pub enum AmbienceLayer {
+    Surface,
+    /// The surface loop as heard from under water
+    Muffled,
+    Underwater,
+}
+
+/// The ambience clips, kept loaded between runs
+#[derive(Resource)]
+struct AmbienceClips {
+    surface: Handle<AudioSource>,
+    underwater: Handle<AudioSource>,
+}
+
+impl FromWorld for AmbienceClips {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world.resource::<AmbienceSettings>().clone();
+        let asset_server = world.resource::<AssetServer>();
+        Self {
+            surface: asset_server.load(settings.surface_clip),
+            underwater: asset_server.load(settings.underwater_clip),
+        }
+    }
+}
+
+/// A clip played through a low-pass filter, muffling everything above `cutoff` hertz
+#[derive(Clone, Debug, TypeUuid, TypePath)]
+#[uuid = "73041758-cb5d-4105-a62f-b9e05ce1a84d"]
+pub struct Muffled {
+    pub clip: AudioSource,
+    pub cutoff: u32,
+}
+
+impl Decodable for Muffled {
+    type DecoderItem = f32;
+    type Decoder = rodio::source::BltFilter<rodio::source::SamplesConverter<rodio::Decoder<Cursor<AudioSource>>, f32>>;
+
+    fn decoder(&self) -> Self::Decoder {
+        rodio::Decoder::new(Cursor::new(self.clip.clone()))
+            .expect("Muffled clip should be decodable")
+            .convert_samples()
+            .low_pass(self.cutoff)
+    }
+}
+
+/// The volume knob of an ambience loop.
+/// Mixing goes through this rather than `AudioSink` directly, so it can be tested without an audio device.
+pub trait AmbienceSink: Component {
+    fn volume(&self) -> f32;
+    fn set_volume(&mut self, volume: f32);
+}
+
+impl AmbienceSink for AudioSink {
+    fn volume(&self) -> f32 {
+        AudioSinkPlayback::volume(self)
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        AudioSinkPlayback::set_volume(self, volume);
+    }
+}
+
+/// How loud a loop should be with the player's head `depth` under water, if at all
+pub fn ambience_target(settings: &AmbienceSettings, layer: AmbienceLayer, depth: Option<f32>) -> f32 {
+    // Even with the head only just under, the water muffles most of the outside
+    let submerged = depth.map_or(0.0, |depth| (0.5 + 0.5 * depth / settings.full_depth).clamp(0.0, 1.0));
+    match layer {
+        AmbienceLayer::Surface => settings.surface_volume * (1.0 - submerged),
+        AmbienceLayer::Muffled => settings.surface_volume * submerged,
+        AmbienceLayer::Underwater => settings.underwater_volume * submerged,
+    }
+}
+
+/// Starts the loops once their clips are loaded.
+/// The muffled loop is made from the surface clip, and starts along with it to stay in step.
+fn spawn_ambience(
+    mut commands: Commands,
+    settings: Res<AmbienceSettings>,
+    clips: Res<AmbienceClips>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut muffled_sources: ResMut<Assets<Muffled>>,
+    layer_query: Query<(), With<AmbienceLayer>>,
+) {
+    if !layer_query.is_empty() {
+        return;
+    }
+    let Some(surface_clip) = audio_sources.get(&clips.surface) else { return; };
+
+    let looping = |volume: f32| PlaybackSettings::LOOP.with_volume(Volume::new_relative(volume));
+    let muffled = muffled_sources.add(Muffled {
+        clip: surface_clip.clone(),
+        cutoff: settings.muffled_cutoff,
+    });
+
+    commands.spawn((
+        AudioBundle {
+            source: clips.surface.clone(),
+            settings: looping(settings.surface_volume),
+        },
+        AmbienceLayer::Surface,
+        InGame {},
+    ));
+    commands.spawn((
+        AudioSourceBundle {
+            source: muffled,
+            settings: looping(0.0),
+        },
+        AmbienceLayer::Muffled,
+        InGame {},
+    ));
+    commands.spawn((
+        AudioBundle {
+            source: clips.underwater.clone(),
+            settings: looping(0.0),
+        },
+        AmbienceLayer::Underwater,
+        InGame {},
+    ));
+}
+
+fn pause_ambience(sink_query: Query<&AudioSink, With<AmbienceLayer>>) {
+    for sink in sink_query.iter() {
+        sink.pause();
+    }
+}
+
+fn resume_ambience(sink_query: Query<&AudioSink, With<AmbienceLayer>>) {
+    for sink in sink_query.iter() {
+        sink.play();
+    }
+}
+
+pub fn crossfade_ambience<S: AmbienceSink>(
+    time: Res<Time>,
+    settings: Res<AmbienceSettings>,
+    player_query: Query<(&Position, Has<InWater>), With<Player>>,
+    water_query: Query<(&Position, &MergedRect), With<WaterBody>>,
+    mut sink_query: Query<(&AmbienceLayer, &mut S)>,
+) {
+    let Ok((position, in_water)) = player_query.get_single() else { return; };
+
+    let depth = if in_water {
+        let water: Vec<Rect> = water_query
+            .iter()
+            .map(|(water_position, rect)| Rect::from_center_size(water_position.0, rect.size))
+            .collect();
+        depth_below_surface(position.0, &water)
+    } else {
+        None
+    };
+
+    // Exponential smoothing, so fades take the same time at any frame rate
+    let blend = 1.0 - (-settings.fade_rate * time.delta_seconds()).exp();
+
+    for (layer, mut sink) in sink_query.iter_mut() {
+        let target = ambience_target(&settings, *layer, depth);
+        let volume = sink.volume();
+        sink.set_volume(volume + (target - volume) * blend);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+    use std::time::Duration;
+    use bevy::time::TimeUpdateStrategy;
+    use super::*;
+
+    #[derive(Component)]
+    struct TestSink(f32);
+
+    impl AmbienceSink for TestSink {
+        fn volume(&self) -> f32 {
+            self.0
+        }
+
+        fn set_volume(&mut self, volume: f32) {
+            self.0 = volume;
+        }
+    }
+
+    fn targets(settings: &AmbienceSettings, depth: Option<f32>) -> (f32, f32, f32) {
+        (
+            ambience_target(settings, AmbienceLayer::Surface, depth),
+            ambience_target(settings, AmbienceLayer::Muffled, depth),
+            ambience_target(settings, AmbienceLayer::Underwater, depth),
+        )
+    }
+
+    #[test]
+    fn targets_follow_depth() {
+        let settings = AmbienceSettings::default();
+        let surface = settings.surface_volume;
+        let underwater = settings.underwater_volume;
+
+        assert_eq!(targets(&settings, None), (surface, 0.0, 0.0));
+        assert_eq!(targets(&settings, Some(0.0)), (surface * 0.5, surface * 0.5, underwater * 0.5));
+        assert_eq!(targets(&settings, Some(settings.full_depth)), (0.0, surface, underwater));
+        assert_eq!(targets(&settings, Some(settings.full_depth * 3.0)), (0.0, surface, underwater));
+    }
+
+    /// A mono 16 bit WAV clip of `samples`
+    fn wav(sample_rate: u32, samples: &[i16]) -> AudioSource {
+        let data_size = samples.len() as u32 * 2;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        AudioSource { bytes: bytes.into() }
+    }
+
+    fn loudness(samples: impl Iterator<Item = f32>) -> f32 {
+        let samples: Vec<f32> = samples.collect();
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn muffling_quiets_high_frequencies() {
+        let sample_rate = 44100;
+        let tone = |frequency: f32| -> Vec<i16> {
+            (0..sample_rate)
+                .map(|i| ((i as f32 * frequency * TAU / sample_rate as f32).sin() * 16000.0) as i16)
+                .collect()
+        };
+        let muffled_loudness = |frequency: f32| {
+            let clip = wav(sample_rate, &tone(frequency));
+            let dry = loudness(clip.decoder().convert_samples::<f32>());
+            let muffled = loudness(Muffled { clip, cutoff: 400 }.decoder());
+            muffled / dry
+        };
+
+        assert!(muffled_loudness(50.0) > 0.9);
+        assert!(muffled_loudness(4000.0) < 0.1);
+    }
+
+    fn volumes(app: &mut App) -> (f32, f32, f32) {
+        let mut volumes = (0.0, 0.0, 0.0);
+        for (layer, sink) in app.world.query::<(&AmbienceLayer, &TestSink)>().iter(&app.world) {
+            match layer {
+                AmbienceLayer::Surface => volumes.0 = sink.0,
+                AmbienceLayer::Muffled => volumes.1 = sink.0,
+                AmbienceLayer::Underwater => volumes.2 = sink.0,
+            }
+        }
+        volumes
+    }
+
+    #[test]
+    fn crossfades_towards_the_water_and_back() {
+        let mut app = App::new();
+        app
+            .add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(100)))
+            .init_resource::<AmbienceSettings>()
+            .add_systems(Update, crossfade_ambience::<TestSink>);
+
+        let settings = AmbienceSettings::default();
+        app.world.spawn((AmbienceLayer::Surface, TestSink(settings.surface_volume)));
+        app.world.spawn((AmbienceLayer::Muffled, TestSink(0.0)));
+        app.world.spawn((AmbienceLayer::Underwater, TestSink(0.0)));
+        app.world.spawn((
+            Position::from(Vec2::new(50.0, 50.0)),
+            MergedRect { size: Vec2::new(100.0, 100.0) },
+            WaterBody::default(),
+        ));
+        let player = app.world.spawn((Player {}, Position::from(Vec2::new(50.0, 10.0)), InWater {})).id();
+
+        // The first update only starts the clock
+        app.update();
+        let mut previous = volumes(&mut app);
+        for _ in 0..10 {
+            app.update();
+            let current = volumes(&mut app);
+            assert!(current.0 < previous.0 && current.1 > previous.1 && current.2 > previous.2, "{previous:?} to {current:?}");
+            previous = current;
+        }
+        // Deep under, the surface is nearly silent
+        assert!(previous.0 < settings.surface_volume * 0.1);
+
+        app.world.entity_mut(player).remove::<InWater>();
+        for _ in 0..10 {
+            app.update();
+            let current = volumes(&mut app);
+            assert!(current.0 > previous.0 && current.1 < previous.1 && current.2 < previous.2, "{previous:?} to {current:?}");
+            previous = current;
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use rand::Rng;
+use crate::oxygen::{Oxygen, OxygenSettings};
+use crate::water::InWater;
+use crate::win::WinSensor;
+use crate::levels::{LevelEntered, LevelExited, LevelIndex};
+use crate::{Player, WallImpact};
+
+/// The game camera: follows the player, zooms with the situation,
+/// shakes on impacts and can be pointed elsewhere for a moment with `CinematicFocus`.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraSettings>()
+            .add_systems(Startup, spawn_camera)
+            .add_systems(Update, (
+                choose_zoom,
+                add_trauma,
+                reveal_win,
+                camera_follow,
+                (zoom_camera, shake_camera),
+            ).chain());
+    }
+}
+
+#[derive(Component)]
+pub struct CameraFollow {}
+
+/// The camera, and the point it is currently settling towards
+#[derive(Component, Default)]
+pub struct GameCam {
+    pub focus: Vec2,
+    /// Where the camera is before shaking is added on top
+    pub position: Vec2,
+}
+
+/// Zoom and shake state of the camera
+#[derive(Component, Clone, Debug)]
+pub struct CameraController {
+    /// 1.0 is the default view, higher shows more of the level
+    pub zoom: f32,
+    pub target_zoom: f32,
+    /// How quickly the zoom approaches the target, per second
+    pub zoom_rate: f32,
+    /// 0.0 to 1.0, shake grows with the square of this
+    pub trauma: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            target_zoom: 1.0,
+            zoom_rate: 2.0,
+            trauma: 0.0,
+        }
+    }
+}
+
+/// Points the camera at `point` instead of the followed entity for a while
+#[derive(Component, Clone, Debug)]
+pub struct CinematicFocus {
+    pub point: Vec2,
+    pub timer: Timer,
+}
+
+/// How big a world pixel is on screen at zoom 1.0
+const SCREEN_PIXELS_PER_PIXEL: f32 = 8.0;
+
+#[derive(Resource, Clone, Debug)]
+pub struct CameraSettings {
+    /// How quickly the camera catches up with its focus, per second.
+    /// Higher is snappier, and the result is the same at any frame rate.
+    pub smoothing: f32,
+    /// Half size of the box around the focus the followed entity can move in
+    /// without dragging the camera along, in pixels
+    pub dead_zone: Vec2,
+    /// Seconds of velocity to look ahead by
+    pub look_ahead: f32,
+    /// Furthest the camera looks ahead, in pixels
+    pub max_look_ahead: f32,
+    /// Zoom while the player is out of the water, under water and running out of air
+    pub surfaced_zoom: f32,
+    pub submerged_zoom: f32,
+    pub drowning_zoom: f32,
+    /// Furthest the camera is thrown by a shake at full trauma, in pixels
+    pub max_shake: f32,
+    /// Trauma lost per second
+    pub trauma_decay: f32,
+    /// Impacts at this speed, in meters per second, give full trauma
+    pub full_trauma_speed: f32,
+    /// How long the win tile is shown when a level starts
+    pub reveal_win_seconds: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            smoothing: 5.0,
+            dead_zone: Vec2::new(32.0, 32.0),
+            look_ahead: 0.3,
+            max_look_ahead: 128.0,
+            surfaced_zoom: 1.4,
+            submerged_zoom: 1.0,
+            drowning_zoom: 0.7,
+            max_shake: 24.0,
+            trauma_decay: 1.5,
+            full_trauma_speed: 40.0,
+            reveal_win_seconds: 2.5,
+        }
+    }
+}
+
+pub fn spawn_camera(mut commands: Commands) {
+    commands.spawn((
+        Camera2dBundle {
+            projection: OrthographicProjection {
+                scale: 1.0,
+                near: 0.0,
+                far: 1000.0,
+                viewport_origin: Vec2::new(0.5, 0.5),
+                scaling_mode: ScalingMode::WindowSize(SCREEN_PIXELS_PER_PIXEL),
+                area: Rect::new(-1.0, -1.0, 1.0, 1.0),
+            },
+            ..default()
+        },
+        GameCam::default(),
+        CameraController::default(),
+    )
+    );
+}
+
+/// Follows the `CameraFollow` entity, looking ahead of it in the direction it is moving,
+/// and never shows anything outside the level it is in.
+/// While there is a `CinematicFocus`, its point is followed instead.
+pub fn camera_follow(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    to_follow: Query<(&Transform, Option<&LinearVelocity>), (With<CameraFollow>, Without<GameCam>)>,
+    mut camera: Query<(
+        Entity,
+        &mut Transform,
+        &mut GameCam,
+        &OrthographicProjection,
+        Option<&mut CinematicFocus>,
+    ), Without<CameraFollow>>,
+    level_index: Res<LevelIndex>,
+) {
+    let Ok((camera_entity, mut camera_transform, mut game_cam, projection, cinematic)) = camera.get_single_mut() else { return; };
+
+    let followed = match cinematic {
+        Some(mut cinematic) => {
+            if cinematic.timer.tick(time.delta()).finished() {
+                commands.entity(camera_entity).remove::<CinematicFocus>();
+            }
+            game_cam.focus = cinematic.point;
+            cinematic.point
+        }
+        None => {
+            let Ok((followed_transform, velocity)) = to_follow.get_single() else { return; };
+            let followed = followed_transform.translation.truncate();
+            let look_ahead = velocity
+                .map_or(Vec2::ZERO, |velocity| velocity.0 * settings.look_ahead)
+                .clamp_length_max(settings.max_look_ahead);
+            let target = followed + look_ahead;
+
+            // Only drag the focus along once the target leaves the dead zone around it
+            let offset = target - game_cam.focus;
+            game_cam.focus += offset - offset.clamp(-settings.dead_zone, settings.dead_zone);
+            followed
+        }
+    };
+
+    let level_bounds = level_index.level_at(followed).map(|level| level.bounds);
+
+    // Clamping before smoothing glides the camera over to the bounds of the next level
+    // instead of snapping to them when the followed entity crosses into it
+    let target = match level_bounds {
+        Some(bounds) => clamp_to_bounds(game_cam.focus, projection.area.half_size(), bounds),
+        None => game_cam.focus,
+    };
+
+    let blend = 1.0 - (-settings.smoothing * time.delta_seconds()).exp();
+    let position = game_cam.position.lerp(target, blend);
+
+    game_cam.position = position;
+    camera_transform.translation = position.extend(camera_transform.translation.z);
+}
+
+/// Pulls out when the player surfaces and closes in as they run out of air
+pub fn choose_zoom(
+    settings: Res<CameraSettings>,
+    oxygen_settings: Res<OxygenSettings>,
+    player_query: Query<(Has<InWater>, Option<&Oxygen>), With<Player>>,
+    mut controller_query: Query<&mut CameraController>,
+) {
+    let Ok((in_water, oxygen)) = player_query.get_single() else { return; };
+    let Ok(mut controller) = controller_query.get_single_mut() else { return; };
+
+    let drowning = oxygen.is_some_and(|oxygen| oxygen.fraction() <= oxygen_settings.drowning_threshold);
+    controller.target_zoom = match (in_water, drowning) {
+        (_, true) => settings.drowning_zoom,
+        (true, false) => settings.submerged_zoom,
+        (false, false) => settings.surfaced_zoom,
+    };
+}
+
+/// The player slamming into walls shakes the camera
+pub fn add_trauma(
+    settings: Res<CameraSettings>,
+    mut wall_impacts: EventReader<WallImpact>,
+    player_query: Query<(), With<Player>>,
+    mut controller_query: Query<&mut CameraController>,
+) {
+    let Ok(mut controller) = controller_query.get_single_mut() else { return; };
+
+    for impact in wall_impacts.iter() {
+        if player_query.contains(impact.entity) {
+            controller.trauma = (controller.trauma + impact.speed / settings.full_trauma_speed).min(1.0);
+        }
+    }
+}
+
+/// Shows where the way out is when a player appears in a level:
+/// at the start of a run, after completing the level before it and after drowning.
+/// Walking over from a neighbouring level doesn't count.
+///
+/// The win sensor of the level may spawn after the player, so the reveal waits for it,
+/// unless the player leaves the level first.
+pub fn reveal_win(
+    mut commands: Commands,
+    settings: Res<CameraSettings>,
+    mut level_entered: EventReader<LevelEntered>,
+    mut level_exited: EventReader<LevelExited>,
+    player_query: Query<(), With<Player>>,
+    win_query: Query<(&Position, &Parent), With<WinSensor>>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    levels: Res<Assets<LdtkLevel>>,
+    camera_query: Query<Entity, With<GameCam>>,
+    mut pending: Local<Option<String>>,
+) {
+    let exited: Vec<&LevelExited> = level_exited
+        .iter()
+        .filter(|exited| player_query.contains(exited.entity))
+        .collect();
+    if exited.iter().any(|exited| pending.as_ref() == Some(&exited.level_iid)) {
+        *pending = None;
+    }
+
+    for entered in level_entered.iter() {
+        let walked_in = exited.iter().any(|exited| exited.entity == entered.entity);
+        if player_query.contains(entered.entity) && !walked_in {
+            *pending = Some(entered.level_iid.clone());
+        }
+    }
+
+    let Some(level_iid) = pending.as_ref() else { return; };
+    // Win sensors are spawned as children of their level
+    let Some((win_position, _)) = win_query.iter().find(|(_, parent)| {
+        level_query
+            .get(parent.get())
+            .ok()
+            .and_then(|handle| levels.get(handle))
+            .is_some_and(|level| &level.level.iid == level_iid)
+    }) else { return; };
+    let Ok(camera) = camera_query.get_single() else { return; };
+
+    commands.entity(camera).insert(CinematicFocus {
+        point: win_position.0,
+        timer: Timer::from_seconds(settings.reveal_win_seconds, TimerMode::Once),
+    });
+    *pending = None;
+}
+
+pub fn zoom_camera(
+    time: Res<Time>,
+    mut camera_query: Query<(&mut CameraController, &mut OrthographicProjection)>,
+) {
+    for (mut controller, mut projection) in camera_query.iter_mut() {
+        let blend = 1.0 - (-controller.zoom_rate * time.delta_seconds()).exp();
+        controller.zoom += (controller.target_zoom - controller.zoom) * blend;
+        projection.scale = controller.zoom;
+    }
+}
+
+/// Trauma based shake, added on top of the followed position
+pub fn shake_camera(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    mut camera_query: Query<(&mut CameraController, &mut Transform)>,
+) {
+    let mut rng = rand::thread_rng();
+
+    for (mut controller, mut transform) in camera_query.iter_mut() {
+        controller.trauma = (controller.trauma - settings.trauma_decay * time.delta_seconds()).max(0.0);
+        if controller.trauma <= 0.0 {
+            continue;
+        }
+
+        let shake = controller.trauma * controller.trauma * settings.max_shake;
+        transform.translation.x += rng.gen_range(-1.0..=1.0) * shake;
+        transform.translation.y += rng.gen_range(-1.0..=1.0) * shake;
+    }
+}
+
+/// Keeps a view of `half_view` around `position` inside `bounds`,
+/// centering it on any axis where the bounds are smaller than the view
+fn clamp_to_bounds(position: Vec2, half_view: Vec2, bounds: Rect) -> Vec2 {
+    let clamp_axis = |value: f32, half: f32, min: f32, max: f32| {
+        if max - min < half * 2.0 {
+            (min + max) / 2.0
+        } else {
+            value.clamp(min + half, max - half)
+        }
+    };
+
+    Vec2::new(
+        clamp_axis(position.x, half_view.x, bounds.min.x, bounds.max.x),
+        clamp_axis(position.y, half_view.y, bounds.min.y, bounds.max.y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: Rect = Rect {
+        min: Vec2::new(0.0, 0.0),
+        max: Vec2::new(200.0, 100.0),
+    };
+
+    #[test]
+    fn view_inside_bounds_is_unchanged() {
+        assert_eq!(clamp_to_bounds(Vec2::new(100.0, 50.0), Vec2::splat(20.0), BOUNDS), Vec2::new(100.0, 50.0));
+    }
+
+    #[test]
+    fn view_is_kept_inside_bounds() {
+        let half_view = Vec2::new(40.0, 20.0);
+        assert_eq!(clamp_to_bounds(Vec2::new(10.0, 95.0), half_view, BOUNDS), Vec2::new(40.0, 80.0));
+        assert_eq!(clamp_to_bounds(Vec2::new(500.0, -50.0), half_view, BOUNDS), Vec2::new(160.0, 20.0));
+    }
+
+    #[test]
+    fn view_larger_than_bounds_is_centered() {
+        // Too tall, but not too wide
+        let half_view = Vec2::new(60.0, 60.0);
+        assert_eq!(clamp_to_bounds(Vec2::new(10.0, 90.0), half_view, BOUNDS), Vec2::new(60.0, 50.0));
+    }
+}
@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::game_state::GameState;
+use crate::levels::{LevelEntered, LevelProgress, LevelTracker};
+use crate::spawn::{NextSpawn, PlayerStart};
+use crate::units::world_transform;
+use crate::win::LevelCompleted;
+use crate::{player_contacts, Layer, Player};
+
+/// Lets a drowned player try again from the last checkpoint they reached.
+///
+/// Checkpoints are `Checkpoint` entities in the LDtk project, sized in the editor.
+/// Touching one records it together with a snapshot of the `LevelProgress`.
+/// Until then, the start of the level the player is on counts as one.
+///
+/// Trying again after drowning rebuilds the run as usual. The fresh player appears at the
+/// checkpoint with full oxygen, at rest and out of the water until the water says otherwise,
+/// and the levels are back the way they were when the checkpoint was reached.
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<LastCheckpoint>()
+            .register_ldtk_entity::<CheckpointBundle>("Checkpoint")
+            .add_systems(OnExit(GameState::Drowned), restore_checkpoint)
+            .add_systems(OnExit(GameState::Victory), forget_checkpoints)
+            .add_systems(Update, add_checkpoint_sensors)
+            .add_systems(Update, (
+                leave_level_checkpoints,
+                record_level_start,
+                reach_checkpoints,
+            ).chain().run_if(in_state(GameState::Playing)));
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct Checkpoint {
+    /// In pixels
+    pub size: Vec2,
+}
+
+/// Checkpoints double as spawn points, named after their iid
+#[derive(Clone, Debug, Default, Bundle, LdtkEntity)]
+pub struct CheckpointBundle {
+    #[with(checkpoint_size)]
+    checkpoint: Checkpoint,
+    #[with(checkpoint_spawn_point)]
+    player_start: PlayerStart,
+}
+
+fn checkpoint_size(entity_instance: &EntityInstance) -> Checkpoint {
+    Checkpoint {
+        size: Vec2::new(entity_instance.width as f32, entity_instance.height as f32),
+    }
+}
+
+fn checkpoint_spawn_point(entity_instance: &EntityInstance) -> PlayerStart {
+    PlayerStart {
+        name: entity_instance.iid.clone(),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReachedCheckpoint {
+    /// Name of the checkpoint's spawn point, `None` for the level's own start
+    pub spawn: Option<String>,
+    pub level_iid: String,
+}
+
+/// Where the player comes back after drowning, and how the levels looked when they got there
+#[derive(Resource, Clone, Debug, Default)]
+pub struct LastCheckpoint {
+    /// `None` only until the player has entered their first level
+    pub reached: Option<ReachedCheckpoint>,
+    pub progress: LevelProgress,
+}
+
+pub fn add_checkpoint_sensors(
+    mut commands: Commands,
+    checkpoint_query: Query<(Entity, &Checkpoint), Added<Checkpoint>>,
+    transform_query: Query<(&Transform, Option<&Parent>)>,
+) {
+    for (entity, checkpoint) in checkpoint_query.iter() {
+        let position = world_transform(entity, &transform_query).translation.truncate();
+        commands.entity(entity).insert((
+            RigidBody::Static,
+            Collider::cuboid(checkpoint.size.x, checkpoint.size.y),
+            Sensor,
+            Position::from(position),
+            CollisionLayers::new([Layer::Checkpoint], [Layer::Player]),
+        ));
+    }
+}
+
+pub fn reach_checkpoints(
+    mut collision_event_reader: EventReader<CollisionStarted>,
+    player_query: Query<(), With<Player>>,
+    tracker_query: Query<&LevelTracker>,
+    checkpoint_query: Query<&PlayerStart, With<Checkpoint>>,
+    progress: Res<LevelProgress>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+) {
+    for (player, other) in player_contacts(collision_event_reader.iter(), &player_query) {
+        let Ok(tracker) = tracker_query.get(player) else { continue; };
+        let Ok(spawn_point) = checkpoint_query.get(other) else { continue; };
+        let Some(level_iid) = tracker.current.clone() else { continue; };
+
+        *last_checkpoint = LastCheckpoint {
+            reached: Some(ReachedCheckpoint {
+                spawn: Some(spawn_point.name.clone()),
+                level_iid,
+            }),
+            progress: progress.clone(),
+        };
+    }
+}
+
+/// A completed level's checkpoints are left behind
+pub fn leave_level_checkpoints(
+    mut level_completed: EventReader<LevelCompleted>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+) {
+    if level_completed.iter().last().is_some() {
+        last_checkpoint.reached = None;
+    }
+}
+
+/// The level a player spawns into counts as a checkpoint until they reach a real one
+pub fn record_level_start(
+    mut level_entered: EventReader<LevelEntered>,
+    player_query: Query<(), With<Player>>,
+    progress: Res<LevelProgress>,
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+) {
+    for entered in level_entered.iter() {
+        if last_checkpoint.reached.is_some() || !player_query.contains(entered.entity) {
+            continue;
+        }
+
+        *last_checkpoint = LastCheckpoint {
+            reached: Some(ReachedCheckpoint {
+                spawn: None,
+                level_iid: entered.level_iid.clone(),
+            }),
+            progress: progress.clone(),
+        };
+    }
+}
+
+/// Runs as the drowned run is torn down, so the rebuilt one starts from the checkpoint
+pub fn restore_checkpoint(
+    last_checkpoint: Res<LastCheckpoint>,
+    mut progress: ResMut<LevelProgress>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut next_spawn: ResMut<NextSpawn>,
+) {
+    *progress = last_checkpoint.progress.clone();
+
+    if let Some(reached) = &last_checkpoint.reached {
+        *level_selection = LevelSelection::Iid(reached.level_iid.clone());
+        next_spawn.0 = reached.spawn.clone();
+    }
+}
+
+/// A new run starts from scratch
+pub fn forget_checkpoints(
+    mut last_checkpoint: ResMut<LastCheckpoint>,
+    mut progress: ResMut<LevelProgress>,
+) {
+    *last_checkpoint = LastCheckpoint::default();
+    *progress = LevelProgress::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game_state::{despawn_all, InGame};
+    use crate::spawn::tests::{player_position, spawn_app, TestLevel, LEVEL_IID};
+    use super::*;
+
+    #[test]
+    fn drowned_player_respawns_at_the_checkpoint_they_reached() {
+        let mut app = spawn_app();
+        app
+            .add_state::<GameState>()
+            .add_event::<CollisionStarted>()
+            .init_resource::<LevelProgress>()
+            .init_resource::<LastCheckpoint>()
+            .add_systems(OnExit(GameState::Drowned), (despawn_all::<InGame>, restore_checkpoint))
+            .add_systems(Update, reach_checkpoints);
+
+        let origin = Vec2::new(64.0, -280.0);
+        let level = TestLevel::spawn(&mut app, origin, IVec2::new(10, 10));
+        level.spawn_cell(&mut app, GridCoords::new(3, 2), PlayerStart::default());
+        let checkpoint = level.spawn_entity(&mut app, Vec2::new(60.0, 36.0), (
+            Checkpoint { size: Vec2::splat(16.0) },
+            PlayerStart { name: "checkpoint".to_string() },
+        ));
+
+        app.update();
+        let player = app.world.query_filtered::<Entity, With<Player>>().single(&app.world);
+        assert_eq!(player_position(&mut app), origin + Vec2::new(28.0, 20.0));
+
+        app.world.get_mut::<LevelTracker>(player).unwrap().current = Some(LEVEL_IID.to_string());
+        app.world.send_event(CollisionStarted(player, checkpoint));
+        app.update();
+
+        app.world.resource_mut::<NextState<GameState>>().set(GameState::Drowned);
+        app.update();
+        app.world.resource_mut::<NextState<GameState>>().set(GameState::Playing);
+        app.update();
+
+        let respawned = app.world.query_filtered::<Entity, With<Player>>().single(&app.world);
+        assert_ne!(respawned, player);
+        assert_eq!(player_position(&mut app), origin + Vec2::new(60.0, 36.0));
+    }
+}
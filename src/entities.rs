@@ -0,0 +1,398 @@
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::fields::{entity_ref_field, float_field, string_field};
+use crate::game_state::{GameState, InGame};
+use crate::levels::LevelProgress;
+use crate::oxygen::{Drowned, Oxygen};
+use crate::units::world_transform;
+use crate::{player_contacts, Layer, Player};
+
+/// Gameplay objects placed as entities in the LDtk editor.
+///
+/// | Identifier  | Fields                                        |
+/// |-------------|-----------------------------------------------|
+/// | `AirBubble` | `oxygen: Float`                               |
+/// | `Switch`    | `door: EntityRef`                             |
+/// | `Door`      |                                               |
+/// | `Enemy`     | `speed: Float`, `range: Float`, `bite: Float` |
+/// | `Sign`      | `text: String`                                |
+///
+/// Fields are read into components through their `From<&EntityInstance>` impls,
+/// and fields left empty fall back to the defaults of their component.
+/// Sizes come from the entity's size in the editor, and its tile is used as its sprite.
+/// Any other entity is spawned as a plain `MapEntity`, just its sprite.
+///
+/// Collected bubbles and opened doors are recorded in `LevelProgress`,
+/// so they stay gone when their level spawns again.
+pub struct EntitiesPlugin;
+
+impl Plugin for EntitiesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_default_ldtk_entity::<MapEntity>()
+            .register_ldtk_entity::<AirBubbleBundle>("AirBubble")
+            .register_ldtk_entity::<SwitchBundle>("Switch")
+            .register_ldtk_entity::<DoorBundle>("Door")
+            .register_ldtk_entity::<EnemyBundle>("Enemy")
+            .register_ldtk_entity::<SignBundle>("Sign")
+            .add_systems(Update, add_entity_colliders)
+            .add_systems(Update, (
+                collect_air_bubbles,
+                press_switches,
+                patrol_enemies,
+                enemy_bites,
+                read_signs,
+            ).run_if(in_state(GameState::Playing)));
+    }
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct MapEntity {
+    #[sprite_sheet_bundle]
+    #[bundle()]
+    sprite_bundle: SpriteSheetBundle,
+}
+
+/// The size of an entity in the editor, in pixels
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct EntitySize(pub Vec2);
+
+impl From<&EntityInstance> for EntitySize {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        Self(Vec2::new(entity_instance.width as f32, entity_instance.height as f32))
+    }
+}
+
+/// Refills the player's oxygen when touched, then pops
+#[derive(Copy, Clone, Debug, Component)]
+pub struct AirBubble {
+    /// Fraction of a full breath
+    pub oxygen: f32,
+}
+
+impl Default for AirBubble {
+    fn default() -> Self {
+        Self {
+            oxygen: 0.5,
+        }
+    }
+}
+
+impl From<&EntityInstance> for AirBubble {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        Self {
+            oxygen: float_field(&entity_instance.field_instances, "oxygen").unwrap_or(Self::default().oxygen),
+        }
+    }
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct AirBubbleBundle {
+    #[sprite_sheet_bundle]
+    #[bundle()]
+    sprite_bundle: SpriteSheetBundle,
+    #[from_entity_instance]
+    air_bubble: AirBubble,
+    #[from_entity_instance]
+    instance: EntityInstance,
+    #[from_entity_instance]
+    size: EntitySize,
+}
+
+/// Opens its door when touched
+#[derive(Clone, Debug, Default, Component)]
+pub struct Switch {
+    /// Iid of the door
+    pub door: Option<String>,
+}
+
+impl From<&EntityInstance> for Switch {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        Self {
+            door: entity_ref_field(&entity_instance.field_instances, "door"),
+        }
+    }
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct SwitchBundle {
+    #[sprite_sheet_bundle]
+    #[bundle()]
+    sprite_bundle: SpriteSheetBundle,
+    #[from_entity_instance]
+    switch: Switch,
+    #[from_entity_instance]
+    size: EntitySize,
+}
+
+/// A wall that goes away for good once its switch is pressed
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct Door;
+
+#[derive(Bundle, LdtkEntity)]
+pub struct DoorBundle {
+    #[sprite_sheet_bundle]
+    #[bundle()]
+    sprite_bundle: SpriteSheetBundle,
+    door: Door,
+    #[from_entity_instance]
+    instance: EntityInstance,
+    #[from_entity_instance]
+    size: EntitySize,
+}
+
+/// Swims back and forth, and takes a bite out of the player's air on contact
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Enemy {
+    /// In pixels per second
+    pub speed: f32,
+    /// How far it swims either way from where it was placed, in pixels
+    pub range: f32,
+    /// Fraction of a full breath lost per bite
+    pub bite: f32,
+}
+
+impl Default for Enemy {
+    fn default() -> Self {
+        Self {
+            speed: 16.0,
+            range: 32.0,
+            bite: 0.25,
+        }
+    }
+}
+
+impl From<&EntityInstance> for Enemy {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        let defaults = Self::default();
+        Self {
+            speed: float_field(&entity_instance.field_instances, "speed").unwrap_or(defaults.speed),
+            range: float_field(&entity_instance.field_instances, "range").unwrap_or(defaults.range),
+            bite: float_field(&entity_instance.field_instances, "bite").unwrap_or(defaults.bite),
+        }
+    }
+}
+
+/// Where an enemy patrols around, set once it is in the world
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Patrol {
+    pub origin: Vec2,
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct EnemyBundle {
+    #[sprite_sheet_bundle]
+    #[bundle()]
+    sprite_bundle: SpriteSheetBundle,
+    #[from_entity_instance]
+    enemy: Enemy,
+    #[from_entity_instance]
+    size: EntitySize,
+}
+
+/// Shows its text while the player is touching it
+#[derive(Clone, Debug, Default, Component)]
+pub struct Sign {
+    pub text: String,
+}
+
+impl From<&EntityInstance> for Sign {
+    fn from(entity_instance: &EntityInstance) -> Self {
+        Self {
+            text: string_field(&entity_instance.field_instances, "text").unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Bundle, LdtkEntity)]
+pub struct SignBundle {
+    #[sprite_sheet_bundle]
+    #[bundle()]
+    sprite_bundle: SpriteSheetBundle,
+    #[from_entity_instance]
+    sign: Sign,
+    #[from_entity_instance]
+    size: EntitySize,
+}
+
+/// The text of the sign being read
+#[derive(Component)]
+pub struct SignText {
+    pub sign: Entity,
+}
+
+/// Gives the gameplay entities their physics once they are spawned.
+/// Doors are walls, enemies are pushed along by their patrol, and the rest are sensors.
+pub fn add_entity_colliders(
+    mut commands: Commands,
+    entity_query: Query<(Entity, &EntitySize, Has<Door>, Has<Enemy>), Added<EntitySize>>,
+    transform_query: Query<(&Transform, Option<&Parent>)>,
+) {
+    for (entity, size, door, enemy) in entity_query.iter() {
+        // Positions are global, the entity's transform is within its layer
+        let position = world_transform(entity, &transform_query).translation.truncate();
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.insert((
+            Collider::cuboid(size.0.x, size.0.y),
+            Position::from(position),
+        ));
+
+        if door {
+            entity_commands.insert((
+                RigidBody::Static,
+                CollisionLayers::new([Layer::Walls], [Layer::Player]),
+            ));
+        } else if enemy {
+            entity_commands.insert((
+                RigidBody::Kinematic,
+                Patrol { origin: position },
+                CollisionLayers::new([Layer::Enemy], [Layer::Player]),
+            ));
+        } else {
+            entity_commands.insert((
+                RigidBody::Static,
+                Sensor,
+                CollisionLayers::new([Layer::Interactive], [Layer::Player]),
+            ));
+        }
+    }
+}
+
+pub fn collect_air_bubbles(
+    mut commands: Commands,
+    mut collision_event_reader: EventReader<CollisionStarted>,
+    player_query: Query<(), With<Player>>,
+    mut oxygen_query: Query<&mut Oxygen>,
+    bubble_query: Query<(&AirBubble, &EntityInstance)>,
+    mut progress: ResMut<LevelProgress>,
+) {
+    for (player, other) in player_contacts(collision_event_reader.iter(), &player_query) {
+        let Ok((bubble, instance)) = bubble_query.get(other) else { continue; };
+        // A bubble popped earlier this frame is still there until commands are applied
+        if !progress.removed.insert(instance.iid.clone()) {
+            continue;
+        }
+
+        if let Ok(mut oxygen) = oxygen_query.get_mut(player) {
+            oxygen.current = (oxygen.current + bubble.oxygen * oxygen.max).min(oxygen.max);
+        }
+        commands.entity(other).despawn_recursive();
+    }
+}
+
+pub fn press_switches(
+    mut commands: Commands,
+    mut collision_event_reader: EventReader<CollisionStarted>,
+    player_query: Query<(), With<Player>>,
+    switch_query: Query<&Switch>,
+    door_query: Query<(Entity, &EntityInstance), With<Door>>,
+    mut progress: ResMut<LevelProgress>,
+) {
+    for (_, other) in player_contacts(collision_event_reader.iter(), &player_query) {
+        let Some(door_iid) = switch_query.get(other).ok().and_then(|switch| switch.door.as_ref()) else { continue; };
+
+        // The door may be in a level that isn't spawned, it is then gone by the time it spawns
+        progress.removed.insert(door_iid.clone());
+        for (door, instance) in door_query.iter() {
+            if instance.iid == *door_iid {
+                commands.entity(door).despawn_recursive();
+            }
+        }
+    }
+}
+
+pub fn patrol_enemies(
+    mut enemy_query: Query<(&Enemy, &Patrol, &Position, &mut LinearVelocity)>,
+) {
+    for (enemy, patrol, position, mut velocity) in enemy_query.iter_mut() {
+        let offset = position.x - patrol.origin.x;
+        let direction = if offset >= enemy.range {
+            -1.0
+        } else if offset <= -enemy.range || velocity.x == 0.0 {
+            1.0
+        } else {
+            velocity.x.signum()
+        };
+        velocity.0 = Vec2::new(direction * enemy.speed, 0.0);
+    }
+}
+
+pub fn enemy_bites(
+    mut collision_event_reader: EventReader<CollisionStarted>,
+    player_query: Query<(), With<Player>>,
+    mut oxygen_query: Query<&mut Oxygen>,
+    enemy_query: Query<&Enemy>,
+    mut drowned_events: EventWriter<Drowned>,
+) {
+    for (player, other) in player_contacts(collision_event_reader.iter(), &player_query) {
+        let Ok(enemy) = enemy_query.get(other) else { continue; };
+        let Ok(mut oxygen) = oxygen_query.get_mut(player) else { continue; };
+
+        let before = oxygen.current;
+        oxygen.current = (oxygen.current - enemy.bite * oxygen.max).max(0.0);
+        // Breathing only notices running out of air on its own
+        if before > 0.0 && oxygen.current <= 0.0 {
+            drowned_events.send(Drowned { entity: player });
+        }
+    }
+}
+
+pub fn read_signs(
+    mut commands: Commands,
+    mut started: EventReader<CollisionStarted>,
+    mut ended: EventReader<CollisionEnded>,
+    player_query: Query<(), With<Player>>,
+    sign_query: Query<&Sign>,
+    text_query: Query<(Entity, &SignText)>,
+) {
+    for (_, other) in player_contacts(started.iter(), &player_query) {
+        let Ok(sign) = sign_query.get(other) else { continue; };
+        if text_query.iter().any(|(_, text)| text.sign == other) {
+            continue;
+        }
+
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        position_type: PositionType::Absolute,
+                        bottom: Val::Px(32.0),
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                SignText { sign: other },
+                InGame {},
+            ))
+            .with_children(|node| {
+                node.spawn(
+                    TextBundle::from_section(
+                        sign.text.clone(),
+                        TextStyle {
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                        .with_text_alignment(TextAlignment::Center),
+                );
+            });
+    }
+
+    for CollisionEnded(entity1, entity2) in ended.iter() {
+        for (player, other) in [(*entity1, *entity2), (*entity2, *entity1)] {
+            if !player_query.contains(player) {
+                continue;
+            }
+            for (text_entity, text) in text_query.iter() {
+                if text.sign == other {
+                    commands.entity(text_entity).despawn_recursive();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Reading the custom fields of LDtk levels and entities.
+//!
+//! Every field is optional: a missing field, or one of another type, reads as `None`
+//! and the caller falls back to its default.
+
+use bevy_ecs_ldtk::ldtk::FieldInstance;
+use bevy_ecs_ldtk::prelude::*;
+
+pub fn field<'a>(fields: &'a [FieldInstance], identifier: &str) -> Option<&'a FieldValue> {
+    fields.iter()
+        .find(|field| field.identifier == identifier)
+        .map(|field| &field.value)
+}
+
+pub fn float_field(fields: &[FieldInstance], identifier: &str) -> Option<f32> {
+    match field(fields, identifier)? {
+        FieldValue::Float(value) => *value,
+        _ => None,
+    }
+}
+
+pub fn string_field(fields: &[FieldInstance], identifier: &str) -> Option<String> {
+    match field(fields, identifier)? {
+        FieldValue::String(value) => value.clone(),
+        _ => None,
+    }
+}
+
+/// The iid of the entity referred to
+pub fn entity_ref_field(fields: &[FieldInstance], identifier: &str) -> Option<String> {
+    match field(fields, identifier)? {
+        FieldValue::EntityRef(reference) => reference.as_ref().map(|reference| reference.entity_iid.clone()),
+        _ => None,
+    }
+}
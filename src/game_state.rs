@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::oxygen::Drowned;
+use crate::sequence::AfterOutro;
+use crate::{load_map, Player};
+
+/// The flow of a run: title screen, intro, playing (and pausing),
+/// then the outro and either drowning or winning.
+///
+/// Everything spawned for a run is marked `InGame` and torn down when the run ends,
+/// so restarting is just another state transition.
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_state::<GameState>()
+            .add_systems(OnEnter(GameState::Title), spawn_screen("drown\n\npress space to dive"))
+            .add_systems(OnEnter(GameState::Paused), spawn_screen("paused\n\npress escape to resume"))
+            .add_systems(OnEnter(GameState::Drowned), spawn_screen("you drowned\n\npress space to try again"))
+            .add_systems(OnEnter(GameState::Victory), spawn_screen("you made it out\n\npress space"))
+            .add_systems(OnEnter(GameState::Playing), (load_map, resume_physics))
+            .add_systems(OnExit(GameState::Playing), pause_physics)
+            .add_systems(OnExit(GameState::Drowned), despawn_all::<InGame>)
+            .add_systems(OnExit(GameState::Victory), (despawn_all::<InGame>, reset_level_selection))
+            .add_systems(Update, (
+                start_on_key.run_if(in_state(GameState::Title)),
+                restart_on_key.run_if(in_state(GameState::Drowned)),
+                toggle_pause.run_if(in_state(GameState::Playing).or_else(in_state(GameState::Paused))),
+                drown.run_if(in_state(GameState::Playing)),
+                back_to_title.run_if(in_state(GameState::Victory)),
+            ));
+
+        for state in [GameState::Title, GameState::Paused, GameState::Drowned, GameState::Victory] {
+            app.add_systems(OnExit(state), despawn_all::<Screen>);
+        }
+    }
+}
+
+#[derive(States, Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum GameState {
+    #[default]
+    Title,
+    Intro,
+    Playing,
+    Paused,
+    Outro,
+    Drowned,
+    Victory,
+}
+
+/// Everything belonging to the current run, torn down when the run ends
+#[derive(Component)]
+pub struct InGame {}
+
+/// The full screen text shown on the title, pause, drowned and victory screens
+#[derive(Component)]
+pub struct Screen {}
+
+fn spawn_screen(text: &'static str) -> impl Fn(Commands) {
+    move |mut commands: Commands| {
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                },
+                Screen {},
+            ))
+            .with_children(|screen| {
+                screen.spawn(
+                    TextBundle::from_section(
+                        text,
+                        TextStyle {
+                            font_size: 48.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    )
+                        .with_text_alignment(TextAlignment::Center),
+                );
+            });
+    }
+}
+
+pub fn despawn_all<T: Component>(mut commands: Commands, query: Query<Entity, With<T>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn start_on_key(keys: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.any_just_pressed([KeyCode::Space, KeyCode::Return]) {
+        next_state.set(GameState::Intro);
+    }
+}
+
+fn restart_on_key(keys: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.any_just_pressed([KeyCode::Space, KeyCode::Return]) {
+        next_state.set(GameState::Playing);
+    }
+}
+
+fn toggle_pause(
+    keys: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        next_state.set(match state.get() {
+            GameState::Paused => GameState::Playing,
+            _ => GameState::Paused,
+        });
+    }
+}
+
+fn drown(
+    mut drowned: EventReader<Drowned>,
+    player_query: Query<(), With<Player>>,
+    mut after_outro: ResMut<AfterOutro>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if drowned.iter().any(|event| player_query.contains(event.entity)) {
+        *after_outro = AfterOutro(GameState::Drowned);
+        next_state.set(GameState::Outro);
+    }
+}
+
+fn back_to_title(keys: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.any_just_pressed([KeyCode::Space, KeyCode::Return]) {
+        next_state.set(GameState::Title);
+    }
+}
+
+fn reset_level_selection(mut level_selection: ResMut<LevelSelection>) {
+    *level_selection = LevelSelection::Index(0);
+}
+
+fn pause_physics(mut physics_loop: ResMut<PhysicsLoop>) {
+    physics_loop.pause();
+}
+
+fn resume_physics(mut physics_loop: ResMut<PhysicsLoop>) {
+    physics_loop.resume();
+}
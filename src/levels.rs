@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_ldtk::ldtk::Level;
+use crate::Player;
+
+/// Keeps track of where levels are, which level things are in and what has changed in them.
+///
+/// `LevelIndex` knows the world bounds of every loaded level, whether it is spawned or not.
+/// Entities with a `LevelTracker` get `LevelEntered` and `LevelExited` events as they move
+/// between levels, and `LevelSelection` follows whichever player entered a level last.
+///
+/// Levels are despawned once the player is far enough away and respawn fresh when they come back,
+/// so `LevelProgress` remembers which of their LDtk entities are gone for good.
+pub struct LevelsPlugin;
+
+impl Plugin for LevelsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<LevelIndex>()
+            .init_resource::<LevelProgress>()
+            .add_event::<LevelEntered>()
+            .add_event::<LevelExited>()
+            .add_systems(Update, (index_levels, track_levels, follow_players).chain())
+            .add_systems(Update, despawn_removed_entities);
+    }
+}
+
+/// Size of the buckets levels are sorted into, in pixels.
+/// Looking up a point only checks the levels overlapping its bucket.
+const BUCKET_SIZE: f32 = 256.0;
+
+#[derive(Clone, Debug)]
+pub struct IndexedLevel {
+    pub iid: String,
+    /// In world space, assuming the LDtk world itself sits at the origin
+    pub bounds: Rect,
+    pub handle: Handle<LdtkLevel>,
+}
+
+#[derive(Resource, Default)]
+pub struct LevelIndex {
+    levels: HashMap<HandleId, IndexedLevel>,
+    buckets: HashMap<IVec2, Vec<HandleId>>,
+}
+
+impl LevelIndex {
+    /// The level containing `point`, if any.
+    /// Where levels touch, the point belongs to whichever level comes first.
+    pub fn level_at(&self, point: Vec2) -> Option<&IndexedLevel> {
+        let bucket = (point / BUCKET_SIZE).floor().as_ivec2();
+        self.buckets
+            .get(&bucket)?
+            .iter()
+            .filter_map(|id| self.levels.get(id))
+            .find(|level| level.bounds.contains(point))
+    }
+
+    pub fn level(&self, iid: &str) -> Option<&IndexedLevel> {
+        self.levels.values().find(|level| level.iid == iid)
+    }
+
+    fn insert(&mut self, handle: &Handle<LdtkLevel>, level: &Level) {
+        self.remove(handle.id());
+
+        // LDtk's world goes down from the top left, levels are placed by their bottom left
+        let min = Vec2::new(level.world_x as f32, -(level.world_y + level.px_hei) as f32);
+        let bounds = Rect::from_corners(min, min + Vec2::new(level.px_wid as f32, level.px_hei as f32));
+
+        let first = (bounds.min / BUCKET_SIZE).floor().as_ivec2();
+        let last = (bounds.max / BUCKET_SIZE).floor().as_ivec2();
+        for x in first.x..=last.x {
+            for y in first.y..=last.y {
+                self.buckets.entry(IVec2::new(x, y)).or_default().push(handle.id());
+            }
+        }
+
+        self.levels.insert(handle.id(), IndexedLevel {
+            iid: level.iid.clone(),
+            bounds,
+            handle: handle.clone_weak(),
+        });
+    }
+
+    fn remove(&mut self, id: HandleId) {
+        if self.levels.remove(&id).is_none() {
+            return;
+        }
+        self.buckets.retain(|_, ids| {
+            ids.retain(|other| *other != id);
+            !ids.is_empty()
+        });
+    }
+}
+
+/// Makes an entity send `LevelEntered` and `LevelExited` as it moves between levels
+#[derive(Clone, Debug, Default, Component)]
+pub struct LevelTracker {
+    /// The iid of the level the entity is in
+    pub current: Option<String>,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct LevelEntered {
+    pub entity: Entity,
+    pub level_iid: String,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct LevelExited {
+    pub entity: Entity,
+    pub level_iid: String,
+}
+
+/// State of the levels that outlives their entities
+#[derive(Resource, Clone, Debug, Default)]
+pub struct LevelProgress {
+    /// Iids of LDtk entities that have been collected, destroyed or otherwise taken out of their level
+    pub removed: HashSet<String>,
+}
+
+/// Whether `level` is the one `selection` points at.
+/// `LevelSelection::Index` counts levels in project order.
+pub fn is_level_selected(selection: &LevelSelection, level: &Level, project: &LdtkAsset) -> bool {
+    project.project.levels
+        .iter()
+        .position(|other| other.iid == level.iid)
+        .is_some_and(|index| selection.is_match(&index, level))
+}
+
+pub fn index_levels(
+    mut asset_events: EventReader<AssetEvent<LdtkLevel>>,
+    levels: Res<Assets<LdtkLevel>>,
+    mut index: ResMut<LevelIndex>,
+) {
+    // A level can be changed several times in one frame, only its latest state matters
+    let mut changed = HashSet::new();
+
+    for asset_event in asset_events.iter() {
+        match asset_event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                changed.insert(handle.clone_weak());
+            }
+            AssetEvent::Removed { handle } => {
+                changed.remove(handle);
+                index.remove(handle.id());
+            }
+        }
+    }
+
+    for handle in changed {
+        if let Some(ldtk_level) = levels.get(&handle) {
+            index.insert(&handle, &ldtk_level.level);
+        }
+    }
+}
+
+pub fn track_levels(
+    index: Res<LevelIndex>,
+    mut tracker_query: Query<(Entity, &Transform, &mut LevelTracker)>,
+    mut level_entered: EventWriter<LevelEntered>,
+    mut level_exited: EventWriter<LevelExited>,
+) {
+    for (entity, transform, mut tracker) in tracker_query.iter_mut() {
+        // Between levels, the entity stays in the last one it was in
+        let Some(level) = index.level_at(transform.translation.truncate()) else { continue; };
+        if tracker.current.as_ref() == Some(&level.iid) {
+            continue;
+        }
+
+        if let Some(previous) = tracker.current.replace(level.iid.clone()) {
+            level_exited.send(LevelExited { entity, level_iid: previous });
+        }
+        level_entered.send(LevelEntered { entity, level_iid: level.iid.clone() });
+    }
+}
+
+/// Selects the level a player just entered.
+///
+/// With several players, the selection follows whoever entered a level last.
+/// The levels next to it stay spawned, so players in neighbouring levels are safe.
+pub fn follow_players(
+    mut level_entered: EventReader<LevelEntered>,
+    player_query: Query<(), With<Player>>,
+    mut level_selection: ResMut<LevelSelection>,
+) {
+    let Some(entered) = level_entered
+        .iter()
+        .filter(|entered| player_query.contains(entered.entity))
+        .last() else { return; };
+
+    let selection = LevelSelection::Iid(entered.level_iid.clone());
+    if *level_selection != selection {
+        *level_selection = selection;
+    }
+}
+
+/// Keeps entities removed from their level gone when the level spawns again
+pub fn despawn_removed_entities(
+    mut commands: Commands,
+    progress: Res<LevelProgress>,
+    entity_query: Query<(Entity, &EntityInstance), Added<EntityInstance>>,
+) {
+    for (entity, instance) in entity_query.iter() {
+        if progress.removed.contains(&instance.iid) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Indexes a level at `world` in LDtk's coordinates, which go down from the top left
+    fn insert_level(index: &mut LevelIndex, iid: &str, world: IVec2, size: IVec2) -> HandleId {
+        let handle = Handle::weak(HandleId::random::<LdtkLevel>());
+        index.insert(&handle, &Level {
+            iid: iid.to_string(),
+            world_x: world.x,
+            world_y: world.y,
+            px_wid: size.x,
+            px_hei: size.y,
+            ..default()
+        });
+        handle.id()
+    }
+
+    fn iid_at(index: &LevelIndex, point: Vec2) -> Option<&str> {
+        index.level_at(point).map(|level| level.iid.as_str())
+    }
+
+    #[test]
+    fn level_at_finds_levels_across_buckets() {
+        let mut index = LevelIndex::default();
+        insert_level(&mut index, "first", IVec2::new(0, 0), IVec2::new(216, 280));
+        insert_level(&mut index, "second", IVec2::new(216, 0), IVec2::new(300, 280));
+
+        // LDtk's y goes down, the world's goes up
+        assert_eq!(index.level("first").unwrap().bounds, Rect::new(0.0, -280.0, 216.0, 0.0));
+
+        assert_eq!(iid_at(&index, Vec2::new(100.0, -100.0)), Some("first"));
+        assert_eq!(iid_at(&index, Vec2::new(100.0, -270.0)), Some("first"));
+        assert_eq!(iid_at(&index, Vec2::new(400.0, -100.0)), Some("second"));
+        assert_eq!(iid_at(&index, Vec2::new(514.0, -10.0)), Some("second"));
+        assert_eq!(iid_at(&index, Vec2::new(100.0, 10.0)), None);
+        assert_eq!(iid_at(&index, Vec2::new(600.0, -100.0)), None);
+    }
+
+    #[test]
+    fn removed_levels_are_not_found() {
+        let mut index = LevelIndex::default();
+        let first = insert_level(&mut index, "first", IVec2::new(0, 0), IVec2::new(600, 600));
+        insert_level(&mut index, "second", IVec2::new(600, 0), IVec2::new(100, 100));
+
+        index.remove(first);
+        assert_eq!(iid_at(&index, Vec2::new(100.0, -100.0)), None);
+        assert_eq!(iid_at(&index, Vec2::new(650.0, -50.0)), Some("second"));
+        assert!(index.level("first").is_none());
+        assert!(index.buckets.values().flatten().all(|id| *id != first));
+    }
+}
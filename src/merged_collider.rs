@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_ldtk::ldtk::Type;
+use crate::outline::trace_outlines;
+use crate::rect_merge::MergeMode;
+use crate::units::{grid_corner_to_pixel, grid_rect_to_pixel, world_transform};
+
+/// Spawns merged colliders for every IntGrid tile marked with `T`.
+///
+/// You could just insert a collider in to the tile's bundle,
+/// but this spawns a different collider for EVERY tile.
+/// This approach leads to bad performance.
+///
+/// Instead, by flagging the tiles and spawning the collisions later,
+/// we can minimize the amount of colliding entities.
+///
+/// Adding a new kind of tile is one registration:
+/// ```ignore
+/// app.add_plugins(
+///     MergedColliderPlugin::<Lava>::new(CollisionLayers::new([Layer::Lava], [Layer::Player]))
+///         .sensor()
+///         .with_extra(Lava)
+/// );
+/// ```
+pub struct MergedColliderPlugin<T: Component, B: Bundle + Clone = ()> {
+    layers: CollisionLayers,
+    sensor: bool,
+    shape: ColliderShape,
+    extra: B,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// What kind of colliders the tiles are merged into
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ColliderShape {
+    /// Solid rectangles, decomposed with the given `MergeMode`
+    Rects(MergeMode),
+    /// One polyline per connected region, tracing its outline.
+    /// There are no internal edges to snag on, but the inside is hollow,
+    /// so this is only useful for solid walls, not sensors.
+    Outline,
+}
+
+impl<T: Component> MergedColliderPlugin<T> {
+    pub fn new(layers: CollisionLayers) -> Self {
+        Self {
+            layers,
+            sensor: false,
+            shape: ColliderShape::Rects(MergeMode::default()),
+            extra: (),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Component, B: Bundle + Clone> MergedColliderPlugin<T, B> {
+    /// Spawn the colliders as sensors instead of solid bodies.
+    /// Panics if the colliders are `outlined`, as outlines are hollow.
+    pub fn sensor(mut self) -> Self {
+        assert!(self.shape != ColliderShape::Outline, "Outlined colliders can't be sensors, they are hollow");
+        self.sensor = true;
+        self
+    }
+
+    /// How the tiles are decomposed into rectangles, `MergeMode::Rows` by default
+    pub fn with_mode(mut self, mode: MergeMode) -> Self {
+        self.shape = ColliderShape::Rects(mode);
+        self
+    }
+
+    /// Trace the outline of each region instead of decomposing it into rectangles.
+    /// Panics if the colliders are sensors, as outlines are hollow.
+    pub fn outlined(mut self) -> Self {
+        assert!(!self.sensor, "Sensors can't be outlined, outlines are hollow");
+        self.shape = ColliderShape::Outline;
+        self
+    }
+
+    /// Components added to every spawned collider, on top of the physics components
+    pub fn with_extra<E: Bundle + Clone>(self, extra: E) -> MergedColliderPlugin<T, E> {
+        MergedColliderPlugin {
+            layers: self.layers,
+            sensor: self.sensor,
+            shape: self.shape,
+            extra,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Component, B: Bundle + Clone> Plugin for MergedColliderPlugin<T, B> {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(MergedColliderConfig::<T, B> {
+                layers: self.layers,
+                sensor: self.sensor,
+                shape: self.shape,
+                extra: self.extra.clone(),
+                _marker: PhantomData,
+            })
+            .add_systems(Update, spawn_merged_colliders::<T, B>);
+    }
+}
+
+#[derive(Resource)]
+pub struct MergedColliderConfig<T: Component, B: Bundle + Clone> {
+    pub layers: CollisionLayers,
+    pub sensor: bool,
+    pub shape: ColliderShape,
+    pub extra: B,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// The size in pixels of a merged collider rectangle
+#[derive(Copy, Clone, Debug, Component)]
+pub struct MergedRect {
+    pub size: Vec2,
+}
+
+/// Groups the tiles per level, merges them according to the configured `ColliderShape`
+/// and spawns a collider for each rectangle or outline.
+pub fn spawn_merged_colliders<T: Component, B: Bundle + Clone>(
+    mut commands: Commands,
+    config: Res<MergedColliderConfig<T, B>>,
+    tile_query: Query<(&GridCoords, &Parent), Added<T>>,
+    parent_query: Query<&Parent, Without<T>>,
+    level_query: Query<(Entity, &Handle<LdtkLevel>)>,
+    transform_query: Query<(&Transform, Option<&Parent>)>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    if tile_query.is_empty() {
+        return;
+    }
+
+    // Consider where the tiles are
+    // storing them as GridCoords in a HashSet for quick, easy lookup
+    //
+    // The key of this map will be the entity of the level the tile belongs to.
+    // This has two consequences in the resulting collision entities:
+    // 1. it forces the colliders to be split along level boundaries
+    // 2. it lets us easily add the collision entities as children of the appropriate level entity
+    let mut level_to_tile_locations: HashMap<Entity, HashSet<GridCoords>> = HashMap::new();
+
+    tile_query.for_each(|(&grid_coords, parent)| {
+        // An intgrid tile's direct parent will be a layer entity, not the level entity
+        // To get the level entity, you need the tile's grandparent.
+        // This is where parent_query comes in.
+        if let Ok(grandparent) = parent_query.get(parent.get()) {
+            level_to_tile_locations
+                .entry(grandparent.get())
+                .or_default()
+                .insert(grid_coords);
+        }
+    });
+
+    level_query.for_each(|(level_entity, level_handle)| {
+        let Some(level_tiles) = level_to_tile_locations.get(&level_entity) else { return; };
+        let level = levels
+            .get(level_handle)
+            .expect("Level should be loaded by this point");
+
+        // The entity layer comes first, above the tiles
+        let &LayerInstance {
+            c_wid: width,
+            c_hei: height,
+            grid_size,
+            ..
+        } = level
+            .level
+            .layer_instances
+            .iter()
+            .flatten()
+            .find(|layer| matches!(layer.layer_instance_type, Type::IntGrid))
+            .expect("Level asset should have an IntGrid layer");
+
+        // Positions are global, while the tiles are laid out from the bottom left of their level
+        let level_origin = world_transform(level_entity, &transform_query).translation.truncate();
+
+        commands.entity(level_entity).with_children(|level| {
+            // Spawn colliders for every rectangle or outline..
+            // Making the collider a child of the level serves two purposes:
+            // 1. Adjusts the transforms to be relative to the level for free
+            // 2. the colliders will be despawned automatically when levels unload
+            match config.shape {
+                ColliderShape::Rects(mode) => {
+                    for rect in mode.merge(level_tiles, width, height) {
+                        let rect = grid_rect_to_pixel(rect, grid_size);
+                        let size = rect.size();
+                        let mut collider = level.spawn((
+                            RigidBody::Static,
+                            // full extents, we're not using rapier
+                            Collider::cuboid(size.x, size.y),
+                            Position::from(level_origin + rect.center()),
+                            config.layers,
+                            MergedRect { size },
+                            config.extra.clone(),
+                        ));
+                        if config.sensor {
+                            collider.insert(Sensor);
+                        }
+                    }
+                }
+                ColliderShape::Outline => {
+                    for outline in trace_outlines(level_tiles, width, height) {
+                        let mut vertices: Vec<Vec2> = Vec::new();
+                        let mut indices: Vec<[u32; 2]> = Vec::new();
+                        for outline_loop in &outline.loops {
+                            let first = vertices.len() as u32;
+                            let count = outline_loop.len() as u32;
+                            vertices.extend(outline_loop.iter().map(|corner| grid_corner_to_pixel(*corner, grid_size)));
+                            indices.extend((0..count).map(|i| [first + i, first + (i + 1) % count]));
+                        }
+
+                        let mut collider = level.spawn((
+                            RigidBody::Static,
+                            Collider::polyline(vertices, Some(indices)),
+                            Position::from(level_origin),
+                            config.layers,
+                            config.extra.clone(),
+                        ));
+                        if config.sensor {
+                            collider.insert(Sensor);
+                        }
+                    }
+                }
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Component)]
+    struct Tile;
+
+    #[test]
+    #[should_panic]
+    fn outlined_sensors_are_rejected() {
+        let _ = MergedColliderPlugin::<Tile>::new(CollisionLayers::default()).sensor().outlined();
+    }
+
+    #[test]
+    #[should_panic]
+    fn outlines_cannot_become_sensors() {
+        let _ = MergedColliderPlugin::<Tile>::new(CollisionLayers::default()).outlined().sensor();
+    }
+}
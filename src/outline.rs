@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use bevy::prelude::IVec2;
+use bevy_ecs_ldtk::prelude::GridCoords;
+
+/// The outline of one connected region of cells.
+///
+/// Every loop is a closed polygon of grid corners, without repeated or collinear points.
+/// A region has one outer loop plus one loop for each hole in it.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Outline {
+    pub loops: Vec<Vec<IVec2>>,
+}
+
+/// Traces the outline of every 4-connected region of cells in a `width` x `height` grid.
+/// Cells outside the grid are ignored.
+///
+/// Corner `(x, y)` is the bottom left corner of cell `(x, y)`.
+pub fn trace_outlines(cells: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<Outline> {
+    let in_grid = |c: &GridCoords| (0..width).contains(&c.x) && (0..height).contains(&c.y);
+    let mut unvisited: HashSet<GridCoords> = cells.iter().copied().filter(in_grid).collect();
+    let mut outlines = Vec::new();
+
+    // Walk the cells in a fixed order so the result doesn't depend on HashSet ordering
+    let mut ordered: Vec<GridCoords> = unvisited.iter().copied().collect();
+    ordered.sort_by_key(|c| (c.y, c.x));
+
+    for start in ordered {
+        if !unvisited.remove(&start) {
+            continue;
+        }
+
+        // flood fill the region
+        let mut region = vec![start];
+        let mut frontier = vec![start];
+        while let Some(cell) = frontier.pop() {
+            for neighbour in neighbours(cell) {
+                if unvisited.remove(&neighbour) {
+                    region.push(neighbour);
+                    frontier.push(neighbour);
+                }
+            }
+        }
+
+        let region: HashSet<GridCoords> = region.into_iter().collect();
+        outlines.push(Outline { loops: trace_region(&region) });
+    }
+
+    outlines
+}
+
+fn neighbours(cell: GridCoords) -> [GridCoords; 4] {
+    [
+        GridCoords { x: cell.x + 1, y: cell.y },
+        GridCoords { x: cell.x - 1, y: cell.y },
+        GridCoords { x: cell.x, y: cell.y + 1 },
+        GridCoords { x: cell.x, y: cell.y - 1 },
+    ]
+}
+
+/// Collects the boundary edges of a region, directed counter-clockwise around the cells,
+/// and chains them into loops
+fn trace_region(region: &HashSet<GridCoords>) -> Vec<Vec<IVec2>> {
+    let mut edges: HashMap<IVec2, Vec<IVec2>> = HashMap::new();
+    let mut ordered: Vec<&GridCoords> = region.iter().collect();
+    ordered.sort_by_key(|c| (c.y, c.x));
+
+    for cell in ordered {
+        let corner = IVec2::new(cell.x, cell.y);
+        let filled = |dx: i32, dy: i32| region.contains(&GridCoords { x: cell.x + dx, y: cell.y + dy });
+
+        if !filled(0, -1) {
+            edges.entry(corner).or_default().push(corner + IVec2::X);
+        }
+        if !filled(1, 0) {
+            edges.entry(corner + IVec2::X).or_default().push(corner + IVec2::ONE);
+        }
+        if !filled(0, 1) {
+            edges.entry(corner + IVec2::ONE).or_default().push(corner + IVec2::Y);
+        }
+        if !filled(-1, 0) {
+            edges.entry(corner + IVec2::Y).or_default().push(corner);
+        }
+    }
+
+    let mut starts: Vec<IVec2> = edges.keys().copied().collect();
+    starts.sort_by_key(|corner| (corner.y, corner.x));
+
+    let mut loops = Vec::new();
+    for start in starts {
+        // Where regions pinch together at a corner, a corner starts two edges,
+        // so keep starting loops from it until all its edges are used
+        while let Some(mut next) = edges.get_mut(&start).and_then(|ends| ends.pop()) {
+            let mut points = vec![start];
+            while next != start {
+                points.push(next);
+                next = edges
+                    .get_mut(&next)
+                    .and_then(|ends| ends.pop())
+                    .expect("Boundary edges should always form closed loops");
+            }
+            loops.push(simplify(points));
+        }
+    }
+
+    loops
+}
+
+/// Removes the points of a closed loop that lie on a straight line
+/// between their neighbours
+fn simplify(points: Vec<IVec2>) -> Vec<IVec2> {
+    let count = points.len();
+    (0..count)
+        .filter(|&i| {
+            let previous = points[(i + count - 1) % count];
+            let next = points[(i + 1) % count];
+            (points[i] - previous).perp_dot(next - points[i]) != 0
+        })
+        .map(|i| points[i])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(coords: &[(i32, i32)]) -> HashSet<GridCoords> {
+        coords.iter().map(|&(x, y)| GridCoords { x, y }).collect()
+    }
+
+    /// Twice the signed area of a loop, positive when counter-clockwise
+    fn doubled_area(points: &[IVec2]) -> i32 {
+        (0..points.len())
+            .map(|i| points[i].perp_dot(points[(i + 1) % points.len()]))
+            .sum()
+    }
+
+    fn perimeter(points: &[IVec2]) -> i32 {
+        (0..points.len())
+            .map(|i| {
+                let edge = points[(i + 1) % points.len()] - points[i];
+                edge.x.abs() + edge.y.abs()
+            })
+            .sum()
+    }
+
+    fn boundary_edges(region: &HashSet<GridCoords>) -> i32 {
+        region.iter()
+            .flat_map(|cell| neighbours(*cell))
+            .filter(|neighbour| !region.contains(neighbour))
+            .count() as i32
+    }
+
+    /// Every loop is axis aligned, with no repeated or collinear points
+    fn assert_simplified(outline: &Outline) {
+        for points in &outline.loops {
+            assert!(points.len() >= 4);
+            for i in 0..points.len() {
+                let previous = points[(i + points.len() - 1) % points.len()];
+                let next = points[(i + 1) % points.len()];
+                let (incoming, outgoing) = (points[i] - previous, next - points[i]);
+                assert!(incoming.x == 0 || incoming.y == 0);
+                assert_ne!(incoming.perp_dot(outgoing), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn single_cell() {
+        let outlines = trace_outlines(&cells(&[(2, 3)]), 8, 8);
+        assert_eq!(outlines, vec![Outline {
+            loops: vec![vec![IVec2::new(2, 3), IVec2::new(3, 3), IVec2::new(3, 4), IVec2::new(2, 4)]],
+        }]);
+    }
+
+    #[test]
+    fn separate_regions_and_cells_outside_the_grid() {
+        let outlines = trace_outlines(&cells(&[(0, 0), (1, 0), (5, 5), (-1, 0), (8, 2)]), 8, 8);
+        assert_eq!(outlines.len(), 2);
+        assert_eq!(outlines[0].loops, vec![vec![IVec2::new(0, 0), IVec2::new(2, 0), IVec2::new(2, 1), IVec2::new(0, 1)]]);
+        assert_eq!(outlines[1].loops.len(), 1);
+    }
+
+    #[test]
+    fn hole() {
+        // A 3x3 square with its centre missing
+        let region = cells(&[(0, 0), (1, 0), (2, 0), (0, 1), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        let outlines = trace_outlines(&region, 8, 8);
+        assert_eq!(outlines.len(), 1);
+        assert_simplified(&outlines[0]);
+
+        // The outside goes counter-clockwise, the hole clockwise
+        let mut areas: Vec<i32> = outlines[0].loops.iter().map(|points| doubled_area(points)).collect();
+        areas.sort();
+        assert_eq!(areas, vec![-2, 18]);
+    }
+
+    #[test]
+    fn diagonal_pinch() {
+        // The gap in the middle touches the outside only at the corner (2, 1)
+        //   X X X
+        //   X . X
+        //   X X .
+        let region = cells(&[(0, 0), (1, 0), (0, 1), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        let outlines = trace_outlines(&region, 8, 8);
+        assert_eq!(outlines.len(), 1);
+        assert_simplified(&outlines[0]);
+
+        // Every boundary edge is used exactly once, and the loops enclose exactly the region
+        let loops = &outlines[0].loops;
+        assert_eq!(loops.iter().map(|points| perimeter(points)).sum::<i32>(), boundary_edges(&region));
+        assert_eq!(loops.iter().map(|points| doubled_area(points)).sum::<i32>(), 2 * region.len() as i32);
+
+        let pinch = IVec2::new(2, 1);
+        assert_eq!(loops.iter().flatten().filter(|&&corner| corner == pinch).count(), 2);
+    }
+}
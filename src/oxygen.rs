@@ -0,0 +1,142 @@
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::fields::float_field;
+use crate::game_state::GameState;
+use crate::levels::{LevelEntered, LevelIndex};
+use crate::water::{InAirPocket, InWater};
+use crate::Player;
+
+/// Keeps track of how much air everything with an `Oxygen` component has left.
+///
+/// Air drains while `InWater` is present and refills otherwise,
+/// including while the head is up in an air pocket.
+/// The rates come from `OxygenSettings`, which levels can override
+/// through float fields on the level in the LDtk project:
+/// `oxygen_drain`, `oxygen_refill` and `drowning_threshold`.
+pub struct OxygenPlugin;
+
+impl Plugin for OxygenPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<OxygenSettings>()
+            .add_event::<Drowning>()
+            .add_event::<Drowned>()
+            .add_systems(Update, apply_level_oxygen_settings)
+            .add_systems(Update, breathe
+                .after(apply_level_oxygen_settings)
+                .run_if(in_state(GameState::Playing)));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct OxygenSettings {
+    /// Oxygen lost per second while in water
+    pub drain_rate: f32,
+    /// Oxygen regained per second while out of water
+    pub refill_rate: f32,
+    /// Below this fraction of max oxygen, `Drowning` is sent
+    pub drowning_threshold: f32,
+}
+
+impl Default for OxygenSettings {
+    fn default() -> Self {
+        Self {
+            drain_rate: 8.0,
+            refill_rate: 30.0,
+            drowning_threshold: 0.25,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Oxygen {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Oxygen {
+    pub fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.current / self.max
+    }
+}
+
+impl Default for Oxygen {
+    fn default() -> Self {
+        Self::new(100.0)
+    }
+}
+
+/// Sent once when an entity's oxygen falls below the drowning threshold
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Drowning {
+    pub entity: Entity,
+}
+
+/// Sent once when an entity runs out of oxygen entirely
+#[derive(Event, Clone, Copy, Debug)]
+pub struct Drowned {
+    pub entity: Entity,
+}
+
+pub fn breathe(
+    time: Res<Time>,
+    settings: Res<OxygenSettings>,
+    mut query: Query<(Entity, &mut Oxygen, Has<InWater>, Has<InAirPocket>)>,
+    mut drowning_events: EventWriter<Drowning>,
+    mut drowned_events: EventWriter<Drowned>,
+) {
+    for (entity, mut oxygen, in_water, in_air_pocket) in query.iter_mut() {
+        let before = oxygen.fraction();
+
+        let change = if in_water && !in_air_pocket {
+            -settings.drain_rate
+        } else {
+            settings.refill_rate
+        } * time.delta_seconds();
+        oxygen.current = (oxygen.current + change).clamp(0.0, oxygen.max);
+
+        let after = oxygen.fraction();
+        if before > settings.drowning_threshold && after <= settings.drowning_threshold {
+            drowning_events.send(Drowning { entity });
+        }
+        if before > 0.0 && after <= 0.0 {
+            drowned_events.send(Drowned { entity });
+        }
+    }
+}
+
+/// Reads the oxygen overrides of a level when a player enters it,
+/// falling back to the defaults for anything the level doesn't set.
+///
+/// Neighbouring levels are spawned ahead of the player,
+/// so spawning a level is no sign of the player being in it.
+pub fn apply_level_oxygen_settings(
+    mut level_entered: EventReader<LevelEntered>,
+    player_query: Query<(), With<Player>>,
+    level_index: Res<LevelIndex>,
+    levels: Res<Assets<LdtkLevel>>,
+    mut settings: ResMut<OxygenSettings>,
+) {
+    for entered in level_entered.iter() {
+        if !player_query.contains(entered.entity) {
+            continue;
+        }
+        let Some(ldtk_level) = level_index
+            .level(&entered.level_iid)
+            .and_then(|level| levels.get(&level.handle)) else { continue; };
+
+        let fields = &ldtk_level.level.field_instances;
+
+        let defaults = OxygenSettings::default();
+        *settings = OxygenSettings {
+            drain_rate: float_field(fields, "oxygen_drain").unwrap_or(defaults.drain_rate),
+            refill_rate: float_field(fields, "oxygen_refill").unwrap_or(defaults.refill_rate),
+            drowning_threshold: float_field(fields, "drowning_threshold").unwrap_or(defaults.drowning_threshold),
+        };
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashSet;
+use std::f32::consts::{PI, TAU};
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::game_state::{GameState, InGame};
+use crate::merged_collider::MergedRect;
+use crate::swim::{SwimInput, SwimSettings, Swimmer};
+use crate::units::{grid_to_pixel, world_transform};
+use crate::water::{submerged_area, Buoyant, InWater, WaterBody, WaterContacts};
+use crate::{Layer, Wall, HEAD_SIZE};
+
+/// The body, arms and legs hanging off the player's head.
+///
+/// Every part is its own rigid body, joined to the body with angle limited
+/// `RevoluteJoint`s, and floats and drags in the water just like the head.
+/// The body is spawned turned whichever way it fits between the walls around the head.
+/// While swimming, the limbs are driven through a stroke by torques,
+/// and the water pushing back on them is what moves the swimmer along.
+pub struct RagdollPlugin;
+
+impl Plugin for RagdollPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (drive_limbs, steer_torso, limb_propulsion).run_if(in_state(GameState::Playing)))
+            .add_systems(Update, (untangle_parts, despawn_orphaned_parts));
+    }
+}
+
+/// Any entity belonging to a ragdoll, bodies and joints alike
+#[derive(Copy, Clone, Debug, Component)]
+pub struct RagdollPart {
+    pub head: Entity,
+}
+
+/// The part the limbs are jointed to
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Torso;
+
+/// A limb that pushes against the water when it moves broadside through it
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Paddle {
+    /// How much water the limb catches, in square units
+    pub area: f32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LimbKind {
+    Arm,
+    Leg,
+}
+
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Limb {
+    pub kind: LimbKind,
+    /// -1.0 for the left limb, 1.0 for the right one
+    pub side: f32,
+    /// The torso the limb is jointed to
+    pub body: Entity,
+}
+
+/// How hard limbs are pulled towards their stroke angle
+const LIMB_STIFFNESS: f32 = 82.0;
+/// How much limbs resist spinning relative to the body
+const LIMB_DAMPING: f32 = 16.4;
+/// How far limbs swing either way over a stroke, in radians
+const ARM_SWING: f32 = PI * 0.6;
+const LEG_SWING: f32 = PI * 0.2;
+/// How hard the torso turns to face the swim direction
+const STEER_STIFFNESS: f32 = 205.0;
+const STEER_DAMPING: f32 = 41.0;
+/// Pressure drag on a paddle, per square pixel of area and squared speed
+const PADDLE_DRAG: f32 = 0.25;
+
+struct PartSpec {
+    texture: &'static str,
+    /// Sprite size in pixels
+    size: Vec2,
+    /// Where the part's joint sits on the torso, in torso pixels
+    torso_anchor: Vec2,
+    /// Where the part's joint sits on the part itself, in part pixels
+    part_anchor: Vec2,
+    /// Allowed angle of the part relative to the torso, in radians
+    limits: (f32, f32),
+    z: f32,
+}
+
+const TORSO_SIZE_PX: Vec2 = Vec2::new(7.0, 19.0);
+
+/// Angles the whole ragdoll is tried at when spawning, from hanging straight down to standing on its head
+const SPAWN_ANGLES: [f32; 12] = [
+    0.0,
+    PI / 6.0, -PI / 6.0,
+    PI / 3.0, -PI / 3.0,
+    PI / 2.0, -PI / 2.0,
+    PI * 2.0 / 3.0, -PI * 2.0 / 3.0,
+    PI * 5.0 / 6.0, -PI * 5.0 / 6.0,
+    PI,
+];
+
+/// A part spawned where it doesn't fit between the walls.
+/// It passes through walls until it fits, instead of being pushed out to the wrong side.
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Tangled {
+    /// Sprite size in pixels
+    pub size: Vec2,
+}
+
+/// Spawns a ragdoll body hanging from `head`, which sits at `head_position`.
+///
+/// The body is turned to the first of `SPAWN_ANGLES` where every part fits between the `walls`,
+/// and the head is turned along with it.
+/// Where the body doesn't fit at any angle, the one with the fewest parts that don't is used,
+/// and those parts start out `Tangled`.
+pub fn spawn_ragdoll(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    head: Entity,
+    head_position: Vec2,
+    walls: &WallCells,
+) {
+    let neck = PartSpec {
+        texture: "sprites/head.png",
+        size: Vec2::splat(HEAD_SIZE),
+        torso_anchor: Vec2::new(0.0, TORSO_SIZE_PX.y / 2.0),
+        part_anchor: Vec2::new(0.0, -HEAD_SIZE / 2.0),
+        limits: (-PI / 4.0, PI / 4.0),
+        z: 0.0,
+    };
+    let torso_offset = neck.part_anchor - neck.torso_anchor;
+
+    let limbs = [
+        (LimbKind::Arm, -1.0, PartSpec {
+            texture: "sprites/arms.png",
+            size: Vec2::new(4.0, 20.0),
+            torso_anchor: Vec2::new(-2.5, 8.0),
+            part_anchor: Vec2::new(0.0, 9.0),
+            limits: (-PI, PI / 6.0),
+            z: 0.8,
+        }),
+        (LimbKind::Arm, 1.0, PartSpec {
+            texture: "sprites/arms.png",
+            size: Vec2::new(4.0, 20.0),
+            torso_anchor: Vec2::new(2.5, 8.0),
+            part_anchor: Vec2::new(0.0, 9.0),
+            limits: (-PI / 6.0, PI),
+            z: 1.0,
+        }),
+        (LimbKind::Leg, -1.0, PartSpec {
+            texture: "sprites/leg.png",
+            size: Vec2::new(5.0, 28.0),
+            torso_anchor: Vec2::new(-1.5, -8.5),
+            part_anchor: Vec2::new(0.0, 13.0),
+            limits: (-PI / 3.0, PI / 6.0),
+            z: 0.85,
+        }),
+        (LimbKind::Leg, 1.0, PartSpec {
+            texture: "sprites/leg.png",
+            size: Vec2::new(5.0, 28.0),
+            torso_anchor: Vec2::new(1.5, -8.5),
+            part_anchor: Vec2::new(0.0, 13.0),
+            limits: (-PI / 6.0, PI / 3.0),
+            z: 0.95,
+        }),
+    ];
+
+    // Where every part sits relative to the head when hanging straight down, torso first
+    let layout: Vec<(Vec2, Vec2)> = std::iter::once((torso_offset, TORSO_SIZE_PX))
+        .chain(limbs.iter().map(|(_, _, spec)| (torso_offset + spec.torso_anchor - spec.part_anchor, spec.size)))
+        .collect();
+    let fitting_parts = |angle: f32| -> Vec<bool> {
+        layout
+            .iter()
+            .map(|(offset, size)| {
+                let position = head_position + Vec2::from_angle(angle).rotate(*offset);
+                walls.fits(head_position, position, angle, *size)
+            })
+            .collect()
+    };
+    // `min_by_key` keeps the first of equals, so the body hangs as straight as it can
+    let (angle, fitting) = SPAWN_ANGLES
+        .iter()
+        .map(|&angle| (angle, fitting_parts(angle)))
+        .min_by_key(|(_, fitting)| fitting.iter().filter(|&&fits| !fits).count())
+        .expect("There should be angles to try");
+
+    commands.entity(head).insert((
+        Rotation::from_radians(angle),
+        Transform::from_translation(head_position.extend(1.0)).with_rotation(Quat::from_rotation_z(angle)),
+    ));
+    let place = |offset: Vec2, z: f32| (head_position + Vec2::from_angle(angle).rotate(offset)).extend(z);
+
+    let torso = spawn_part(commands, asset_server, head, "sprites/body.png", TORSO_SIZE_PX, place(torso_offset, 0.9), angle);
+    commands.entity(torso).insert(Torso);
+    spawn_joint(commands, head, torso, head, &neck);
+
+    let mut parts = vec![(torso, TORSO_SIZE_PX)];
+    for (kind, side, spec) in limbs {
+        let translation = place(torso_offset + spec.torso_anchor - spec.part_anchor, spec.z);
+        let limb = spawn_part(commands, asset_server, head, spec.texture, spec.size, translation, angle);
+        commands.entity(limb).insert((
+            Limb { kind, side, body: torso },
+            Paddle { area: spec.size.x * spec.size.y },
+        ));
+        spawn_joint(commands, head, torso, limb, &spec);
+        parts.push((limb, spec.size));
+    }
+
+    for ((part, size), fits) in parts.into_iter().zip(fitting) {
+        if !fits {
+            commands.entity(part).insert((Tangled { size }, part_layers(true)));
+        }
+    }
+}
+
+fn spawn_part(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    head: Entity,
+    texture: &'static str,
+    size: Vec2,
+    translation: Vec3,
+    angle: f32,
+) -> Entity {
+    commands
+        .spawn((
+            SpriteBundle {
+                transform: Transform::from_translation(translation).with_rotation(Quat::from_rotation_z(angle)),
+                texture: asset_server.load(texture),
+                ..default()
+            },
+            RagdollPart { head },
+            InGame {},
+            RigidBody::Dynamic,
+            Position::from(translation.truncate()),
+            Rotation::from_radians(angle),
+            ExternalForce::default().with_persistence(false),
+            ExternalTorque::default().with_persistence(false),
+            Collider::capsule(size.y - size.x, size.x / 2.0),
+            // A ball of the same area floats about as well as the capsule
+            Buoyant { radius: (size.x * size.y / PI).sqrt() },
+            WaterContacts::default(),
+            part_layers(false),
+        ))
+        .id()
+}
+
+fn part_layers(tangled: bool) -> CollisionLayers {
+    if tangled {
+        CollisionLayers::new([Layer::Player], [Layer::Water])
+    } else {
+        CollisionLayers::new([Layer::Player], [Layer::Walls, Layer::Water])
+    }
+}
+
+fn spawn_joint(commands: &mut Commands, head: Entity, torso: Entity, part: Entity, spec: &PartSpec) {
+    commands.spawn((
+        RevoluteJoint::new(torso, part)
+            .with_local_anchor_1(spec.torso_anchor)
+            .with_local_anchor_2(spec.part_anchor)
+            .with_angle_limits(spec.limits.0, spec.limits.1),
+        RagdollPart { head },
+        InGame {},
+    ));
+}
+
+/// The wall cells of the spawned levels, for finding out where ragdoll parts fit.
+/// Levels are assumed to be placed on their grid, as the LDtk editor does by default.
+pub struct WallCells {
+    grid_size: f32,
+    cells: HashSet<IVec2>,
+}
+
+impl WallCells {
+    pub fn collect(
+        wall_query: &Query<(&GridCoords, &Parent), With<Wall>>,
+        layer_query: &Query<(&LayerMetadata, &Parent)>,
+        transform_query: &Query<(&Transform, Option<&Parent>)>,
+    ) -> Self {
+        let mut walls = WallCells {
+            grid_size: 1.0,
+            cells: HashSet::new(),
+        };
+        for (grid_coords, layer) in wall_query.iter() {
+            let Ok((metadata, level)) = layer_query.get(layer.get()) else { continue; };
+            // Cells are placed from the bottom left of their level, just like the wall colliders
+            let level_origin = world_transform(level.get(), transform_query).translation.truncate();
+            let center = level_origin + grid_to_pixel(*grid_coords, metadata.grid_size);
+            walls.grid_size = metadata.grid_size as f32;
+            walls.cells.insert((center / walls.grid_size).floor().as_ivec2());
+        }
+        walls
+    }
+
+    fn contains(&self, point: Vec2) -> bool {
+        self.cells.contains(&(point / self.grid_size).floor().as_ivec2())
+    }
+
+    /// Whether a part of `size`, upright at rotation 0, is clear of the walls
+    /// and on the same side of them as the head it hangs from.
+    ///
+    /// Checks points along the middle and both sides of the part, slightly inset,
+    /// so lying flush against a wall still fits.
+    fn fits(&self, head_position: Vec2, position: Vec2, angle: f32, size: Vec2) -> bool {
+        const SPACING: f32 = 2.0;
+        const INSET: f32 = 0.5;
+
+        let steps = |length: f32| (length / SPACING).ceil().max(1.0) as usize;
+        let half = (size / 2.0 - Vec2::splat(INSET)).max(Vec2::ZERO);
+        let direction = Vec2::from_angle(angle);
+
+        (0..=steps(half.y * 2.0)).all(|step| {
+            let along = -half.y + half.y * 2.0 * step as f32 / steps(half.y * 2.0) as f32;
+            [-half.x, 0.0, half.x].into_iter().all(|across| {
+                let point = position + direction.rotate(Vec2::new(across, along));
+                let sight_steps = steps(point.distance(head_position));
+                (0..=sight_steps).all(|sight_step| {
+                    !self.contains(head_position.lerp(point, sight_step as f32 / sight_steps as f32))
+                })
+            })
+        })
+    }
+}
+
+/// Lets tangled parts collide with walls again once they fit between them
+pub fn untangle_parts(
+    mut commands: Commands,
+    tangled_query: Query<(Entity, &Tangled, &RagdollPart, &Position, &Rotation)>,
+    head_query: Query<&Position, Without<RagdollPart>>,
+    wall_query: Query<(&GridCoords, &Parent), With<Wall>>,
+    layer_query: Query<(&LayerMetadata, &Parent)>,
+    transform_query: Query<(&Transform, Option<&Parent>)>,
+) {
+    if tangled_query.is_empty() {
+        return;
+    }
+
+    let walls = WallCells::collect(&wall_query, &layer_query, &transform_query);
+    for (entity, tangled, part, position, rotation) in tangled_query.iter() {
+        let Ok(head_position) = head_query.get(part.head) else { continue; };
+        if walls.fits(head_position.0, position.0, rotation.as_radians(), tangled.size) {
+            commands.entity(entity).remove::<Tangled>().insert(part_layers(false));
+        }
+    }
+}
+
+/// Swings the limbs through a stroke while the swimmer is stroking,
+/// and lets them hang loose otherwise.
+///
+/// Arms sweep down hard during the power phase of the stroke and come back up slowly,
+/// so the water pushes back harder on the way down than on the way up.
+pub fn drive_limbs(
+    swim_input: Res<SwimInput>,
+    swim_settings: Res<SwimSettings>,
+    swimmer_query: Query<&Swimmer>,
+    body_query: Query<(&Rotation, &AngularVelocity), Without<Limb>>,
+    mut limb_query: Query<(&Limb, &RagdollPart, &Rotation, &AngularVelocity, &mut ExternalTorque)>,
+) {
+    for (limb, part, rotation, angular_velocity, mut torque) in limb_query.iter_mut() {
+        let Ok(swimmer) = swimmer_query.get(part.head) else { continue; };
+        let Ok((body_rotation, body_angular_velocity)) = body_query.get(limb.body) else { continue; };
+
+        // 0.0 at the start of a stroke, 1.0 at its end
+        let phase = 1.0 - swimmer.stroke_timer / swim_settings.stroke_interval;
+        let power = swim_settings.power_phase / swim_settings.stroke_interval;
+        let stroking = swimmer.stroke_timer > 0.0 && swim_input.direction != Vec2::ZERO;
+
+        let target = if !stroking {
+            0.0
+        } else {
+            match limb.kind {
+                // Both arms, mirrored, from raised over the head down to the sides and back
+                LimbKind::Arm => {
+                    let raised = if phase < power {
+                        1.0 - phase / power
+                    } else {
+                        (phase - power) / (1.0 - power)
+                    };
+                    limb.side * ARM_SWING * raised
+                }
+                // Legs kick twice per stroke, alternating
+                LimbKind::Leg => limb.side * LEG_SWING * (phase * 2.0 * TAU).sin(),
+            }
+        };
+
+        let relative = rotation.as_radians() - body_rotation.as_radians();
+        // wrap into -PI..PI so a limb never takes the long way round
+        let error = (target - relative).sin().atan2((target - relative).cos());
+        let spin = angular_velocity.0 - body_angular_velocity.0;
+
+        torque.apply_torque(LIMB_STIFFNESS * error - LIMB_DAMPING * spin);
+    }
+}
+
+/// Turns the torso so the head points where the player wants to swim
+pub fn steer_torso(
+    swim_input: Res<SwimInput>,
+    mut torso_query: Query<(&Rotation, &AngularVelocity, &mut ExternalTorque), (With<Torso>, With<InWater>)>,
+) {
+    if swim_input.direction == Vec2::ZERO {
+        return;
+    }
+
+    for (rotation, angular_velocity, mut torque) in torso_query.iter_mut() {
+        // The torso is upright at rotation 0, so its up is the rotated Y axis
+        let up = Vec2::from_angle(rotation.as_radians()).perp();
+        let error = up.angle_between(swim_input.direction);
+        torque.apply_torque(STEER_STIFFNESS * error - STEER_DAMPING * angular_velocity.0);
+    }
+}
+
+/// Water pushes back on limbs moving broadside through it.
+///
+/// Only the part of the velocity across the limb counts, sliding along its length
+/// catches no water. The push grows with the square of that speed and with
+/// how much of the limb is actually in the water.
+pub fn limb_propulsion(
+    water_query: Query<(&Position, &MergedRect), With<WaterBody>>,
+    mut limb_query: Query<(
+        &mut ExternalForce,
+        &Paddle,
+        &Buoyant,
+        &Position,
+        &Rotation,
+        &LinearVelocity,
+    ), With<InWater>>,
+) {
+    for (mut force, paddle, buoyant, position, rotation, velocity) in limb_query.iter_mut() {
+        let total_area = PI * buoyant.radius * buoyant.radius;
+        let submerged: f32 = water_query
+            .iter()
+            .map(|(water_position, rect)| {
+                submerged_area(position.0, buoyant.radius, Rect::from_center_size(water_position.0, rect.size))
+            })
+            .sum::<f32>()
+            / total_area;
+
+        // Limbs are long along their Y axis, so they face the water along their X axis
+        let normal = Vec2::from_angle(rotation.as_radians());
+        let across = velocity.0.dot(normal);
+
+        force.apply_force(-normal * across * across.abs() * PADDLE_DRAG * paddle.area * submerged.min(1.0));
+    }
+}
+
+/// Ragdoll parts aren't children of the head, physics bodies can't be nested,
+/// so they are cleaned up here once their head is gone
+pub fn despawn_orphaned_parts(
+    mut commands: Commands,
+    part_query: Query<(Entity, &RagdollPart)>,
+    head_query: Query<(), With<Swimmer>>,
+) {
+    for (entity, part) in part_query.iter() {
+        if !head_query.contains(part.head) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+    use crate::spawn::tests::{spawn_app, TestLevel};
+    use super::*;
+
+    #[test]
+    fn wall_cells_cover_their_walls() {
+        let mut app = spawn_app();
+        let origin = Vec2::new(64.0, -280.0);
+        let level = TestLevel::spawn(&mut app, origin, IVec2::new(10, 10));
+        level.spawn_cell(&mut app, GridCoords::new(2, 1), Wall);
+
+        let mut system_state: SystemState<(
+            Query<(&GridCoords, &Parent), With<Wall>>,
+            Query<(&LayerMetadata, &Parent)>,
+            Query<(&Transform, Option<&Parent>)>,
+        )> = SystemState::new(&mut app.world);
+        let (wall_query, layer_query, transform_query) = system_state.get(&app.world);
+        let walls = WallCells::collect(&wall_query, &layer_query, &transform_query);
+
+        assert!(walls.contains(origin + Vec2::new(16.5, 8.5)));
+        assert!(walls.contains(origin + Vec2::new(23.5, 15.5)));
+        assert!(!walls.contains(origin + Vec2::new(24.5, 12.0)));
+        assert!(!walls.contains(origin + Vec2::new(20.0, 16.5)));
+        assert!(!walls.contains(origin + Vec2::new(15.5, 12.0)));
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, HashSet};
+use bevy_ecs_ldtk::prelude::GridCoords;
+
+/// A rectangle of grid cells, inclusive on all sides
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct GridRect {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
+impl GridRect {
+    /// Width in cells
+    pub fn width(&self) -> i32 {
+        self.right - self.left + 1
+    }
+
+    /// Height in cells
+    pub fn height(&self) -> i32 {
+        self.top - self.bottom + 1
+    }
+}
+
+/// Represents a wide run of cells that is 1 cell tall
+#[derive(Clone, Eq, PartialEq, Debug, Default, Hash)]
+struct Plate {
+    left: i32,
+    right: i32,
+}
+
+/// Merges the cells of a `width` x `height` grid into rectangles
+/// that cover every cell exactly once. Cells outside the grid are ignored.
+///
+/// The algorithm used here is a nice compromise between simplicity, speed,
+/// and a small number of rectangles.
+/// In basic terms, it will:
+/// 1. combine cells into flat "plates" in each individual row
+/// 2. combine the plates into rectangles across multiple rows wherever possible
+pub fn merge_rows(cells: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<GridRect> {
+    // combine cells into flat "plates" in each individual row
+    let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
+
+    for y in 0..height {
+        let mut row_plates: Vec<Plate> = Vec::new();
+        let mut plate_start = None;
+
+        // + 1 to the width so the algorithm "terminates" plates that touch the right edge
+        for x in 0..width + 1 {
+            match (plate_start, x < width && cells.contains(&GridCoords { x, y })) {
+                (Some(s), false) => {
+                    row_plates.push(Plate {
+                        left: s,
+                        right: x - 1,
+                    });
+                    plate_start = None;
+                }
+                (None, true) => plate_start = Some(x),
+                _ => (),
+            }
+        }
+
+        plate_stack.push(row_plates);
+    }
+
+    // combine "plates" into rectangles across multiple rows
+    let mut rect_builder: HashMap<Plate, GridRect> = HashMap::new();
+    let mut prev_row: Vec<Plate> = Vec::new();
+    let mut rects: Vec<GridRect> = Vec::new();
+
+    // an extra empty row so the algorithm "finishes" the rects that touch the top edge
+    plate_stack.push(Vec::new());
+
+    for (y, current_row) in plate_stack.into_iter().enumerate() {
+        for prev_plate in &prev_row {
+            if !current_row.contains(prev_plate) {
+                // remove the finished rect so that the same plate in the future starts a new rect
+                if let Some(rect) = rect_builder.remove(prev_plate) {
+                    rects.push(rect);
+                }
+            }
+        }
+        for plate in &current_row {
+            rect_builder
+                .entry(plate.clone())
+                .and_modify(|e| e.top += 1)
+                .or_insert(GridRect {
+                    bottom: y as i32,
+                    top: y as i32,
+                    left: plate.left,
+                    right: plate.right,
+                });
+        }
+        prev_row = current_row;
+    }
+
+    rects
+}
+
+/// Merges the cells of a `width` x `height` grid into rectangles
+/// by repeatedly taking the largest rectangle that fits in the cells not yet covered.
+///
+/// This is slower than `merge_rows`, but it isn't limited to stacking plates
+/// of identical width, so staircases and diagonal shafts end up as
+/// far fewer rectangles with far fewer internal edges.
+pub fn merge_maximal(cells: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<GridRect> {
+    let (w, h) = (width.max(0) as usize, height.max(0) as usize);
+    let mut remaining = vec![false; w * h];
+    for cell in cells {
+        if (0..width).contains(&cell.x) && (0..height).contains(&cell.y) {
+            remaining[cell.y as usize * w + cell.x as usize] = true;
+        }
+    }
+
+    let mut rects = Vec::new();
+    while let Some(rect) = largest_rect(&remaining, w, h) {
+        for y in rect.bottom..=rect.top {
+            for x in rect.left..=rect.right {
+                remaining[y as usize * w + x as usize] = false;
+            }
+        }
+        rects.push(rect);
+    }
+
+    rects
+}
+
+/// Finds the largest rectangle of filled cells, row by row,
+/// using the "largest rectangle in a histogram" stack algorithm
+fn largest_rect(filled: &[bool], width: usize, height: usize) -> Option<GridRect> {
+    let mut column_heights = vec![0usize; width];
+    let mut best: Option<(usize, GridRect)> = None;
+
+    for y in 0..height {
+        for (x, column_height) in column_heights.iter_mut().enumerate() {
+            *column_height = if filled[y * width + x] { *column_height + 1 } else { 0 };
+        }
+
+        let mut stack: Vec<usize> = Vec::new();
+        // one step past the right edge, with height 0, to empty the stack
+        for x in 0..=width {
+            let current = column_heights.get(x).copied().unwrap_or(0);
+            while let Some(&top) = stack.last() {
+                if column_heights[top] < current {
+                    break;
+                }
+                stack.pop();
+
+                let bar_height = column_heights[top];
+                let left = stack.last().map_or(0, |&i| i + 1);
+                let area = bar_height * (x - left);
+                if bar_height > 0 && best.is_none_or(|(best_area, _)| area > best_area) {
+                    best = Some((area, GridRect {
+                        left: left as i32,
+                        right: x as i32 - 1,
+                        top: y as i32,
+                        bottom: (y + 1 - bar_height) as i32,
+                    }));
+                }
+            }
+            stack.push(x);
+        }
+    }
+
+    best.map(|(_, rect)| rect)
+}
+
+/// How the cells of a tile type are decomposed into rectangles
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum MergeMode {
+    /// Fast, merges rows into plates and stacks plates of identical width
+    #[default]
+    Rows,
+    /// Slower, greedily takes the largest rectangle first
+    Maximal,
+}
+
+impl MergeMode {
+    pub fn merge(&self, cells: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<GridRect> {
+        match self {
+            MergeMode::Rows => merge_rows(cells, width, height),
+            MergeMode::Maximal => merge_maximal(cells, width, height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use super::*;
+
+    const MODES: [MergeMode; 2] = [MergeMode::Rows, MergeMode::Maximal];
+
+    fn cells(coords: &[(i32, i32)]) -> HashSet<GridCoords> {
+        coords.iter().map(|&(x, y)| GridCoords { x, y }).collect()
+    }
+
+    /// Panics unless the rects cover every cell of the grid in `cells` exactly once, and nothing else
+    fn assert_exact_cover(rects: &[GridRect], cells: &HashSet<GridCoords>, width: i32, height: i32) {
+        let mut covered = HashSet::new();
+        for rect in rects {
+            assert!(rect.width() > 0 && rect.height() > 0, "{rect:?} is empty");
+            for y in rect.bottom..=rect.top {
+                for x in rect.left..=rect.right {
+                    let cell = GridCoords { x, y };
+                    assert!(cells.contains(&cell), "{rect:?} covers {cell:?}, which isn't filled");
+                    assert!(covered.insert(cell), "{cell:?} is covered twice");
+                }
+            }
+        }
+
+        let in_grid = cells.iter().filter(|c| (0..width).contains(&c.x) && (0..height).contains(&c.y));
+        assert_eq!(covered.len(), in_grid.count());
+    }
+
+    #[test]
+    fn block_is_one_rect() {
+        let block = cells(&[(1, 1), (2, 1), (1, 2), (2, 2), (1, 3), (2, 3)]);
+        for mode in MODES {
+            assert_eq!(mode.merge(&block, 4, 4), vec![GridRect { left: 1, right: 2, bottom: 1, top: 3 }]);
+        }
+    }
+
+    #[test]
+    fn l_shape_is_two_rects() {
+        let l_shape = cells(&[(0, 0), (1, 0), (2, 0), (0, 1), (0, 2)]);
+        for mode in MODES {
+            let rects = mode.merge(&l_shape, 3, 3);
+            assert_eq!(rects.len(), 2);
+            assert_exact_cover(&rects, &l_shape, 3, 3);
+        }
+    }
+
+    #[test]
+    fn cells_outside_the_grid_are_ignored() {
+        let outside = cells(&[(-1, 0), (0, -1), (3, 0), (0, 3), (1, 1)]);
+        for mode in MODES {
+            assert_eq!(mode.merge(&outside, 3, 3), vec![GridRect { left: 1, right: 1, bottom: 1, top: 1 }]);
+        }
+    }
+
+    #[test]
+    fn empty_grid() {
+        for mode in MODES {
+            assert!(mode.merge(&HashSet::new(), 4, 4).is_empty());
+            assert!(mode.merge(&cells(&[(0, 0)]), 0, 0).is_empty());
+        }
+    }
+
+    #[test]
+    fn random_cells_are_covered_exactly_once() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+
+        for _ in 0..500 {
+            let width = rng.gen_range(0..24);
+            let height = rng.gen_range(0..24);
+            let density = rng.gen_range(0.0..=1.0);
+
+            // Some of the cells fall up to 3 cells outside the grid
+            let cells: HashSet<GridCoords> = (-3..width + 3)
+                .flat_map(|x| (-3..height + 3).map(move |y| GridCoords { x, y }))
+                .filter(|_| rng.gen_bool(density))
+                .collect();
+
+            for mode in MODES {
+                assert_exact_cover(&mode.merge(&cells, width, height), &cells, width, height);
+            }
+        }
+    }
+}
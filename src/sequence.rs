@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+use crate::game_state::{despawn_all, GameState};
+
+/// The intro before a run and the outro after it:
+/// a series of text cards fading in and out, each with its own clip.
+///
+/// Any key skips the rest of the sequence.
+pub struct SequencePlugin;
+
+impl Plugin for SequencePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(AfterOutro(GameState::Drowned))
+            .add_systems(OnEnter(GameState::Intro), start_intro)
+            .add_systems(OnEnter(GameState::Outro), start_outro)
+            .add_systems(OnExit(GameState::Intro), (despawn_all::<SequenceCard>, despawn_all::<SequenceAudio>))
+            .add_systems(OnExit(GameState::Outro), (despawn_all::<SequenceCard>, despawn_all::<SequenceAudio>))
+            .add_systems(Update, (play_sequence, skip_sequence)
+                .run_if(in_state(GameState::Intro).or_else(in_state(GameState::Outro))));
+    }
+}
+
+/// Where to go once the outro is over
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct AfterOutro(pub GameState);
+
+struct Card {
+    text: &'static str,
+    clip: &'static str,
+    duration: f32,
+}
+
+const INTRO: &[Card] = &[
+    Card {
+        text: "the shaft was dry when you climbed down",
+        clip: "audio/intro-1.wav",
+        duration: 17.3,
+    },
+    Card {
+        text: "it isn't anymore",
+        clip: "audio/intro-2-echo.wav",
+        duration: 17.4,
+    },
+];
+
+const OUTRO_DROWNED: &[Card] = &[
+    Card {
+        text: "the water keeps what it takes",
+        clip: "audio/outro-echo.wav",
+        duration: 11.7,
+    },
+];
+
+const OUTRO_VICTORY: &[Card] = &[
+    Card {
+        text: "air, at last",
+        clip: "audio/outro-echo.wav",
+        duration: 11.7,
+    },
+];
+
+/// Seconds spent fading a card in, and again fading it out
+const FADE: f32 = 1.0;
+
+#[derive(Resource)]
+pub struct Sequence {
+    cards: &'static [Card],
+    index: usize,
+    elapsed: f32,
+    then: GameState,
+}
+
+#[derive(Component)]
+pub struct SequenceCard {}
+
+#[derive(Component)]
+pub struct SequenceAudio {}
+
+fn start_intro(mut commands: Commands) {
+    commands.insert_resource(Sequence {
+        cards: INTRO,
+        index: 0,
+        elapsed: 0.0,
+        then: GameState::Playing,
+    });
+}
+
+fn start_outro(mut commands: Commands, after_outro: Res<AfterOutro>) {
+    let cards = match after_outro.0 {
+        GameState::Victory => OUTRO_VICTORY,
+        _ => OUTRO_DROWNED,
+    };
+    commands.insert_resource(Sequence {
+        cards,
+        index: 0,
+        elapsed: 0.0,
+        then: after_outro.0,
+    });
+}
+
+fn play_sequence(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut sequence: ResMut<Sequence>,
+    mut text_query: Query<&mut Text, With<SequenceCard>>,
+    card_query: Query<Entity, (With<SequenceCard>, With<Node>, Without<Parent>)>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(card) = sequence.cards.get(sequence.index) else {
+        next_state.set(sequence.then);
+        return;
+    };
+
+    if sequence.elapsed == 0.0 {
+        for entity in card_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_card(&mut commands, card.text);
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load(card.clip),
+                settings: PlaybackSettings::DESPAWN,
+            },
+            SequenceAudio {},
+        ));
+    }
+
+    sequence.elapsed += time.delta_seconds();
+
+    let alpha = (sequence.elapsed / FADE)
+        .min((card.duration - sequence.elapsed) / FADE)
+        .clamp(0.0, 1.0);
+    for mut text in text_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(alpha);
+        }
+    }
+
+    if sequence.elapsed >= card.duration {
+        sequence.index += 1;
+        sequence.elapsed = 0.0;
+    }
+}
+
+fn skip_sequence(
+    keys: Res<Input<KeyCode>>,
+    sequence: Res<Sequence>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keys.get_just_pressed().next().is_some() {
+        next_state.set(sequence.then);
+    }
+}
+
+fn spawn_card(commands: &mut Commands, text: &str) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::BLACK.into(),
+                ..default()
+            },
+            SequenceCard {},
+        ))
+        .with_children(|card| {
+            card.spawn((
+                TextBundle::from_section(
+                    text,
+                    TextStyle {
+                        font_size: 32.0,
+                        color: Color::rgba(1.0, 1.0, 1.0, 0.0),
+                        ..default()
+                    },
+                ),
+                SequenceCard {},
+            ));
+        });
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use bevy::audio::Volume;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use crate::oxygen::{Drowned, Drowning};
+use crate::units::pixels_to_meters;
+use crate::water::{EnteredWater, ExitedWater};
+use crate::{Player, WallImpact};
+
+/// Plays sound effects for gameplay events.
+///
+/// Gameplay systems know nothing about audio: events are turned into `PlaySound`
+/// cues here, and what each cue sounds like is looked up in the `SoundTable`.
+pub struct SfxPlugin;
+
+impl Plugin for SfxPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SoundTable>()
+            .add_event::<PlaySound>()
+            .add_systems(Update, (cue_gameplay_sounds, play_sounds).chain());
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SoundCue {
+    EnterWater,
+    ExitWater,
+    LowOnAir,
+    Drowned,
+    WallImpact,
+}
+
+/// How a cue sounds
+#[derive(Clone, Debug)]
+pub struct SoundEffect {
+    /// One of these is picked at random every time the cue plays
+    pub clips: Vec<&'static str>,
+    /// Seconds after playing during which the cue is ignored
+    pub cooldown: f32,
+    /// Volume is picked at random in this range, then scaled by the cue's intensity
+    pub volume: (f32, f32),
+    /// Playback speed, and with it pitch, is picked at random in this range
+    pub speed: (f32, f32),
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct SoundTable {
+    pub effects: HashMap<SoundCue, SoundEffect>,
+}
+
+impl Default for SoundTable {
+    fn default() -> Self {
+        let effects = [
+            (SoundCue::EnterWater, SoundEffect {
+                clips: vec!["audio/bubbles.wav", "audio/bubbles-1.wav"],
+                cooldown: 0.5,
+                volume: (0.6, 0.9),
+                speed: (0.9, 1.1),
+            }),
+            (SoundCue::ExitWater, SoundEffect {
+                clips: vec!["audio/breath.wav"],
+                cooldown: 2.0,
+                volume: (0.7, 1.0),
+                speed: (0.95, 1.05),
+            }),
+            (SoundCue::LowOnAir, SoundEffect {
+                clips: vec!["audio/diaphragm.wav"],
+                cooldown: 5.0,
+                volume: (0.8, 1.0),
+                speed: (1.0, 1.0),
+            }),
+            (SoundCue::Drowned, SoundEffect {
+                clips: vec!["audio/drown.wav"],
+                cooldown: 0.0,
+                volume: (1.0, 1.0),
+                speed: (1.0, 1.0),
+            }),
+            (SoundCue::WallImpact, SoundEffect {
+                clips: vec!["audio/creak-1.wav"],
+                cooldown: 0.3,
+                volume: (0.3, 0.6),
+                speed: (0.8, 1.2),
+            }),
+        ];
+
+        Self {
+            effects: effects.into_iter().collect(),
+        }
+    }
+}
+
+/// Requests a cue to be played. `intensity` scales the volume, 1.0 is normal.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PlaySound {
+    pub cue: SoundCue,
+    pub intensity: f32,
+}
+
+/// Wall impacts at this speed or faster play at full volume, in meters per second
+const FULL_IMPACT_SPEED: f32 = 20.0;
+/// Entering the water at this speed or faster splashes at full volume, in meters per second
+const FULL_SPLASH_SPEED: f32 = 6.0;
+/// Even sinking into the water slowly makes some noise
+const MIN_SPLASH: f32 = 0.2;
+
+pub fn cue_gameplay_sounds(
+    mut entered_water: EventReader<EnteredWater>,
+    mut exited_water: EventReader<ExitedWater>,
+    mut drowning: EventReader<Drowning>,
+    mut drowned: EventReader<Drowned>,
+    mut wall_impacts: EventReader<WallImpact>,
+    player_query: Query<(), With<Player>>,
+    mut play_sound: EventWriter<PlaySound>,
+) {
+    let mut send = |cue, intensity: f32| play_sound.send(PlaySound { cue, intensity: intensity.min(1.0) });
+
+    // Only the player's head is heard: it does the breathing,
+    // and its limbs splashing and scraping along would drown out everything else
+    let is_player = |entity: &Entity| player_query.contains(*entity);
+
+    for entered in entered_water.iter().filter(|entered| is_player(&entered.entity)) {
+        let speed = pixels_to_meters(entered.velocity.length());
+        send(SoundCue::EnterWater, (speed / FULL_SPLASH_SPEED).max(MIN_SPLASH));
+    }
+    for _ in exited_water.iter().filter(|exited| is_player(&exited.entity)) {
+        send(SoundCue::ExitWater, 1.0);
+    }
+    for _ in drowning.iter().filter(|drowning| is_player(&drowning.entity)) {
+        send(SoundCue::LowOnAir, 1.0);
+    }
+    for _ in drowned.iter().filter(|drowned| is_player(&drowned.entity)) {
+        send(SoundCue::Drowned, 1.0);
+    }
+    for impact in wall_impacts.iter().filter(|impact| is_player(&impact.entity)) {
+        send(SoundCue::WallImpact, impact.speed / FULL_IMPACT_SPEED);
+    }
+}
+
+pub fn play_sounds(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    table: Res<SoundTable>,
+    mut play_sound: EventReader<PlaySound>,
+    mut last_played: Local<HashMap<SoundCue, f32>>,
+) {
+    let now = time.elapsed_seconds();
+    let mut rng = rand::thread_rng();
+
+    for PlaySound { cue, intensity } in play_sound.iter() {
+        let Some(effect) = table.effects.get(cue) else { continue; };
+        if last_played.get(cue).is_some_and(|played| now - played < effect.cooldown) {
+            continue;
+        }
+        let Some(clip) = effect.clips.choose(&mut rng) else { continue; };
+        last_played.insert(*cue, now);
+
+        let volume = rng.gen_range(effect.volume.0..=effect.volume.1) * intensity;
+        let speed = rng.gen_range(effect.speed.0..=effect.speed.1);
+        commands.spawn(AudioBundle {
+            source: asset_server.load(*clip),
+            settings: PlaybackSettings::DESPAWN
+                .with_volume(Volume::new_relative(volume))
+                .with_speed(speed),
+        });
+    }
+}
@@ -0,0 +1,320 @@
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::camera::CameraFollow;
+use crate::fields::string_field;
+use crate::game_state::{GameState, InGame};
+use crate::levels::{is_level_selected, LevelTracker};
+use crate::oxygen::Oxygen;
+use crate::ragdoll::{spawn_ragdoll, WallCells};
+use crate::swim::{LimbPropelled, Swimmer};
+use crate::units::{grid_to_pixel, world_transform};
+use crate::water::{Buoyant, WaterContacts};
+use crate::{Layer, Player, Wall, HEAD_SIZE};
+
+/// Spawns the player at a spawn point of the selected level whenever there is no player.
+///
+/// Spawn points are either `player_start` IntGrid cells, all named `start`,
+/// or `SpawnPoint` entities with a `name` field.
+/// Which one is used is up to `NextSpawn`, then the level's `default_spawn` field,
+/// then `start`, then whichever comes first.
+pub struct SpawnPlugin;
+
+impl Plugin for SpawnPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NextSpawn>()
+            .register_ldtk_int_cell::<PlayerStartBundle>(3)
+            .register_ldtk_entity::<SpawnPointBundle>("SpawnPoint")
+            .add_systems(Update, spawn_player.run_if(in_state(GameState::Playing)));
+    }
+}
+
+/// The name of the `player_start` IntGrid cells
+pub const DEFAULT_SPAWN: &str = "start";
+
+#[derive(Clone, Eq, PartialEq, Debug, Component)]
+pub struct PlayerStart {
+    pub name: String,
+}
+
+impl Default for PlayerStart {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_SPAWN.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct PlayerStartBundle {
+    player_start: PlayerStart,
+}
+
+#[derive(Clone, Debug, Default, Bundle, LdtkEntity)]
+pub struct SpawnPointBundle {
+    #[with(spawn_point_name)]
+    player_start: PlayerStart,
+}
+
+fn spawn_point_name(entity_instance: &EntityInstance) -> PlayerStart {
+    string_field(&entity_instance.field_instances, "name")
+        .map_or_else(PlayerStart::default, |name| PlayerStart { name })
+}
+
+/// The spawn point the next player appears at, if it exists in the selected level.
+/// Consumed when the player spawns.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct NextSpawn(pub Option<String>);
+
+pub fn spawn_player(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level_selection: Res<LevelSelection>,
+    mut next_spawn: ResMut<NextSpawn>,
+    player_query: Query<(), With<Player>>,
+    start_query: Query<(Entity, &PlayerStart, Option<&GridCoords>, &Parent)>,
+    layer_query: Query<(&LayerMetadata, &Parent)>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    wall_query: Query<(&GridCoords, &Parent), With<Wall>>,
+    transform_query: Query<(&Transform, Option<&Parent>)>,
+    project_query: Query<&Handle<LdtkAsset>>,
+    levels: Res<Assets<LdtkLevel>>,
+    projects: Res<Assets<LdtkAsset>>,
+) {
+    if !player_query.is_empty() {
+        return;
+    }
+    let Ok(project_handle) = project_query.get_single() else { return; };
+    let Some(project) = projects.get(project_handle) else { return; };
+
+    let starts: Vec<_> = start_query
+        .iter()
+        .filter_map(|(entity, start, grid_coords, parent)| {
+            // Entities are children of their level, cells are children of a layer within it
+            let (level_entity, position) = if level_query.contains(parent.get()) {
+                (parent.get(), world_transform(entity, &transform_query).translation.truncate())
+            } else {
+                let (layer, layer_parent) = layer_query.get(parent.get()).ok()?;
+                let level_entity = layer_parent.get();
+                // Cells are placed from the bottom left of their level, just like the walls
+                let level_origin = world_transform(level_entity, &transform_query).translation.truncate();
+                (level_entity, level_origin + grid_to_pixel(*grid_coords?, layer.grid_size))
+            };
+
+            let level = levels.get(level_query.get(level_entity).ok()?)?;
+            if !is_level_selected(&level_selection, &level.level, project) {
+                return None;
+            }
+            Some((start, position, level))
+        })
+        .collect();
+
+    let Some((_, _, level)) = starts.first() else { return; };
+    let default_spawn = string_field(&level.level.field_instances, "default_spawn");
+
+    let find = |name: &str| starts.iter().find(|(start, _, _)| start.name == name);
+    let Some((_, head_position, _)) = next_spawn.0.take()
+        .and_then(|name| find(&name))
+        .or_else(|| default_spawn.and_then(|name| find(&name)))
+        .or_else(|| find(DEFAULT_SPAWN))
+        .or(starts.first()) else { return; };
+    let head_position = *head_position;
+
+    let head_radius = HEAD_SIZE / 2.0;
+    let head = commands.spawn(
+        (
+            CameraFollow {},
+            InGame {},
+            SpriteBundle {
+                transform: Transform::from_translation(head_position.extend(1.0)),
+                texture: asset_server.load("sprites/head.png"),
+                ..default()
+            },
+            // Nested, as tuple bundles only go up to 15 components
+            (Player {}, LevelTracker::default()),
+            Swimmer::default(),
+            LimbPropelled,
+            Oxygen::default(),
+            RigidBody::Dynamic,
+            Position::from(head_position),
+            ExternalForce::default().with_persistence(false),
+            ExternalTorque::default().with_persistence(false),
+            Collider::ball(head_radius),
+            Buoyant { radius: head_radius },
+            WaterContacts::default(),
+            CollisionLayers::new([Layer::Player], [
+                Layer::Walls,
+                Layer::Water,
+                Layer::Win,
+                Layer::Checkpoint,
+                Layer::Enemy,
+                Layer::Interactive,
+            ]),
+        )
+    ).id();
+
+    let walls = WallCells::collect(&wall_query, &layer_query, &transform_query);
+    spawn_ragdoll(&mut commands, &asset_server, head, head_position, &walls);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use bevy::asset::AssetPlugin;
+    use bevy_ecs_ldtk::ldtk::{LayerInstance, LdtkJson, Level, Type};
+    use crate::merged_collider::{MergedColliderPlugin, MergedRect};
+    use super::*;
+
+    pub const LEVEL_IID: &str = "level";
+    pub const GRID_SIZE: i32 = 8;
+
+    /// A level spawned the way bevy_ecs_ldtk spawns them.
+    /// IntGrid cells go in its layer, entities go in the level itself.
+    pub struct TestLevel {
+        pub level: Entity,
+        pub layer: Entity,
+    }
+
+    impl TestLevel {
+        /// Spawns the only level of the project, with its bottom left at `origin`
+        pub fn spawn(app: &mut App, origin: Vec2, size: IVec2) -> Self {
+            let level = Level {
+                iid: LEVEL_IID.to_string(),
+                px_wid: size.x * GRID_SIZE,
+                px_hei: size.y * GRID_SIZE,
+                layer_instances: Some(vec![LayerInstance {
+                    layer_instance_type: Type::IntGrid,
+                    c_wid: size.x,
+                    c_hei: size.y,
+                    grid_size: GRID_SIZE,
+                    ..default()
+                }]),
+                ..default()
+            };
+            let project = LdtkAsset {
+                project: LdtkJson {
+                    levels: vec![level.clone()],
+                    ..default()
+                },
+                tileset_map: default(),
+                level_map: default(),
+                int_grid_image_handle: None,
+            };
+            let project = app.world.resource_mut::<Assets<LdtkAsset>>().add(project);
+            let level = app.world.resource_mut::<Assets<LdtkLevel>>().add(LdtkLevel { level, background_image: None });
+
+            app.world.spawn(project);
+            let level = app.world.spawn((level, Transform::from_translation(origin.extend(0.0)))).id();
+            // Layers are moved half a cell, because their tiles are centered on their transforms
+            let layer = app.world.spawn((
+                LayerMetadata {
+                    c_wid: size.x,
+                    c_hei: size.y,
+                    grid_size: GRID_SIZE,
+                    layer_instance_type: Type::IntGrid,
+                    ..default()
+                },
+                Transform::from_translation(Vec2::splat(GRID_SIZE as f32 / 2.0).extend(2.0)),
+            )).set_parent(level).id();
+            Self { level, layer }
+        }
+
+        pub fn spawn_cell(&self, app: &mut App, coords: GridCoords, bundle: impl Bundle) -> Entity {
+            let translation = Vec2::new(coords.x as f32, coords.y as f32) * GRID_SIZE as f32;
+            app.world
+                .spawn((bundle, coords, Transform::from_translation(translation.extend(0.0))))
+                .set_parent(self.layer)
+                .id()
+        }
+
+        /// Spawns an entity at `position` from the bottom left of the level
+        pub fn spawn_entity(&self, app: &mut App, position: Vec2, bundle: impl Bundle) -> Entity {
+            app.world
+                .spawn((bundle, Transform::from_translation(position.extend(3.0))))
+                .set_parent(self.level)
+                .id()
+        }
+    }
+
+    /// An app that spawns the player in the level selected by `LEVEL_IID`
+    pub fn spawn_app() -> App {
+        let mut app = App::new();
+        app
+            .add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_asset::<LdtkAsset>()
+            .add_asset::<LdtkLevel>()
+            .insert_resource(LevelSelection::Iid(LEVEL_IID.to_string()))
+            .init_resource::<NextSpawn>()
+            .add_systems(Update, spawn_player);
+        app
+    }
+
+    pub fn player_position(app: &mut App) -> Vec2 {
+        app.world
+            .query_filtered::<&Position, With<Player>>()
+            .single(&app.world)
+            .0
+    }
+
+    #[test]
+    fn player_spawns_at_the_center_of_a_start_cell() {
+        let mut app = spawn_app();
+        let origin = Vec2::new(64.0, -280.0);
+        let level = TestLevel::spawn(&mut app, origin, IVec2::new(10, 10));
+        level.spawn_cell(&mut app, GridCoords::new(3, 2), PlayerStart::default());
+
+        app.update();
+        assert_eq!(player_position(&mut app), origin + Vec2::new(28.0, 20.0));
+    }
+
+    #[test]
+    fn player_spawns_at_the_next_spawn_point() {
+        let mut app = spawn_app();
+        let origin = Vec2::new(64.0, -280.0);
+        let level = TestLevel::spawn(&mut app, origin, IVec2::new(10, 10));
+        level.spawn_cell(&mut app, GridCoords::new(3, 2), PlayerStart::default());
+        level.spawn_entity(&mut app, Vec2::new(44.0, 60.0), PlayerStart { name: "ledge".to_string() });
+        app.world.insert_resource(NextSpawn(Some("ledge".to_string())));
+
+        app.update();
+        assert_eq!(player_position(&mut app), origin + Vec2::new(44.0, 60.0));
+        assert!(app.world.resource::<NextSpawn>().0.is_none());
+    }
+
+    #[test]
+    fn player_start_lines_up_with_the_wall_colliders_around_it() {
+        let mut app = spawn_app();
+        app.add_plugins(MergedColliderPlugin::<Wall>::new(CollisionLayers::new([Layer::Walls], [Layer::Player])));
+        let origin = Vec2::new(64.0, -280.0);
+        let level = TestLevel::spawn(&mut app, origin, IVec2::new(3, 3));
+
+        // A floor under the start cell and a wall to its right, as at the bottom of a shaft
+        for (x, y) in [(0, 0), (1, 0), (2, 0), (2, 1), (2, 2)] {
+            level.spawn_cell(&mut app, GridCoords::new(x, y), Wall);
+        }
+        level.spawn_cell(&mut app, GridCoords::new(1, 1), PlayerStart::default());
+        app.update();
+
+        let head = player_position(&mut app);
+        let half_cell = GRID_SIZE as f32 / 2.0;
+        let walls: Vec<Rect> = app.world
+            .query::<(&Position, &MergedRect)>()
+            .iter(&app.world)
+            .map(|(position, rect)| Rect::from_center_size(position.0, rect.size))
+            .collect();
+
+        let floor = walls
+            .iter()
+            .find(|rect| rect.contains(head - Vec2::Y * GRID_SIZE as f32))
+            .expect("There should be a wall under the start");
+        assert_eq!(floor.max.y, head.y - half_cell);
+
+        let wall = walls
+            .iter()
+            .find(|rect| rect.contains(head + Vec2::X * GRID_SIZE as f32))
+            .expect("There should be a wall right of the start");
+        assert_eq!(wall.min.x, head.x + half_cell);
+
+        assert!(walls.iter().all(|rect| !rect.contains(head)));
+    }
+}
@@ -0,0 +1,267 @@
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use crate::game_state::GameState;
+use crate::water::InWater;
+
+/// Turns keyboard and gamepad input into swim strokes on every `Swimmer`.
+///
+/// Input is collected into the `SwimInput` resource first and applied in a
+/// separate system, so the controller can be driven headlessly by inserting
+/// `Input<KeyCode>` / `Axis<GamepadAxis>` values (or `SwimInput` directly).
+pub struct SwimPlugin;
+
+impl Plugin for SwimPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SwimSettings>()
+            .init_resource::<SwimInput>()
+            .add_systems(Update, (read_swim_input, swim).chain().run_if(in_state(GameState::Playing)));
+    }
+}
+
+/// Tunables for the swim controller
+#[derive(Resource, Clone, Debug)]
+pub struct SwimSettings {
+    /// Force applied during the power phase of a stroke under water
+    pub stroke_force: f32,
+    /// Force applied continuously while flailing in the air
+    pub flail_force: f32,
+    /// Seconds from the start of one stroke until the next one may begin
+    pub stroke_interval: f32,
+    /// Seconds at the start of a stroke during which the stroke pushes
+    pub power_phase: f32,
+    /// Gamepad stick values below this are ignored
+    pub dead_zone: f32,
+}
+
+impl Default for SwimSettings {
+    fn default() -> Self {
+        Self {
+            stroke_force: 30720.0,
+            flail_force: 2048.0,
+            stroke_interval: 0.6,
+            power_phase: 0.2,
+            dead_zone: 0.2,
+        }
+    }
+}
+
+/// Swimmers that are pushed through the water by their limbs instead of the stroke force.
+/// Their strokes are still timed here, to drive the limbs with.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct LimbPropelled;
+
+/// The direction the player wants to swim in, length at most 1
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct SwimInput {
+    pub direction: Vec2,
+}
+
+/// Anything that can be steered by the swim controller
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Swimmer {
+    /// Seconds left of the current stroke, zero when ready for a new one
+    pub stroke_timer: f32,
+}
+
+pub fn read_swim_input(
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    settings: Res<SwimSettings>,
+    mut swim_input: ResMut<SwimInput>,
+) {
+    let mut direction = Vec2::ZERO;
+
+    if keys.any_pressed([KeyCode::A, KeyCode::Left]) {
+        direction.x -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::D, KeyCode::Right]) {
+        direction.x += 1.0;
+    }
+    if keys.any_pressed([KeyCode::S, KeyCode::Down]) {
+        direction.y -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::W, KeyCode::Up]) {
+        direction.y += 1.0;
+    }
+
+    for gamepad in gamepads.iter() {
+        let stick = Vec2::new(
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.0),
+            axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.0),
+        );
+        if stick.length() > settings.dead_zone {
+            direction += stick;
+        }
+    }
+
+    swim_input.direction = direction.clamp_length_max(1.0);
+}
+
+/// Under water the swimmer moves in distinct strokes: a short push followed by a glide.
+/// Out of water it can only flail weakly in the input direction.
+pub fn swim(
+    time: Res<Time>,
+    settings: Res<SwimSettings>,
+    swim_input: Res<SwimInput>,
+    mut query: Query<(&mut ExternalForce, &mut Swimmer, Has<InWater>, Has<LimbPropelled>)>,
+) {
+    let direction = swim_input.direction;
+
+    for (mut force, mut swimmer, in_water, limb_propelled) in query.iter_mut() {
+        swimmer.stroke_timer = (swimmer.stroke_timer - time.delta_seconds()).max(0.0);
+
+        if !in_water {
+            force.apply_force(direction * settings.flail_force);
+            continue;
+        }
+
+        if swimmer.stroke_timer <= 0.0 && direction != Vec2::ZERO {
+            swimmer.stroke_timer = settings.stroke_interval;
+        }
+
+        let in_power_phase = swimmer.stroke_timer > settings.stroke_interval - settings.power_phase;
+        if in_power_phase && !limb_propelled {
+            force.apply_force(direction * settings.stroke_force);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::input::gamepad::{GamepadConnection, GamepadConnectionEvent, GamepadInfo};
+    use bevy::input::InputPlugin;
+    use super::*;
+
+    fn input_app() -> App {
+        let mut app = App::new();
+        app
+            .add_plugins(InputPlugin)
+            .init_resource::<SwimSettings>()
+            .init_resource::<SwimInput>()
+            .add_systems(Update, read_swim_input);
+        app
+    }
+
+    fn connect_gamepad(app: &mut App) -> Gamepad {
+        let gamepad = Gamepad::new(0);
+        app.world.resource_mut::<Events<GamepadConnectionEvent>>().send(GamepadConnectionEvent::new(
+            gamepad,
+            GamepadConnection::Connected(GamepadInfo { name: "test".to_string() }),
+        ));
+        app.update();
+        gamepad
+    }
+
+    fn set_stick(app: &mut App, gamepad: Gamepad, stick: Vec2) {
+        let mut axes = app.world.resource_mut::<Axis<GamepadAxis>>();
+        axes.set(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX), stick.x);
+        axes.set(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY), stick.y);
+    }
+
+    #[test]
+    fn keyboard_diagonal_is_clamped() {
+        let mut app = input_app();
+        let mut keys = app.world.resource_mut::<Input<KeyCode>>();
+        keys.press(KeyCode::W);
+        keys.press(KeyCode::D);
+        app.update();
+
+        let direction = app.world.resource::<SwimInput>().direction;
+        assert!((direction.length() - 1.0).abs() < 1e-5);
+        assert!((direction.x - direction.y).abs() < 1e-5);
+    }
+
+    #[test]
+    fn stick_below_dead_zone_is_ignored() {
+        let mut app = input_app();
+        let gamepad = connect_gamepad(&mut app);
+        let dead_zone = app.world.resource::<SwimSettings>().dead_zone;
+
+        set_stick(&mut app, gamepad, Vec2::X * dead_zone * 0.5);
+        app.update();
+        assert_eq!(app.world.resource::<SwimInput>().direction, Vec2::ZERO);
+
+        set_stick(&mut app, gamepad, Vec2::X * (dead_zone + 0.1));
+        app.update();
+        assert!(app.world.resource::<SwimInput>().direction.x > dead_zone);
+    }
+
+    const SETTINGS: SwimSettings = SwimSettings {
+        stroke_force: 10.0,
+        flail_force: 1.0,
+        stroke_interval: 1.0,
+        power_phase: 0.25,
+        dead_zone: 0.2,
+    };
+
+    fn swim_app(in_water: bool) -> (App, Entity) {
+        let mut app = App::new();
+        let mut time = Time::default();
+        let startup = time.startup();
+        time.update_with_instant(startup);
+        app
+            .insert_resource(time)
+            .insert_resource(SETTINGS)
+            .insert_resource(SwimInput { direction: Vec2::X })
+            .add_systems(Update, swim);
+
+        let mut swimmer = app.world.spawn((ExternalForce::default(), Swimmer::default()));
+        if in_water {
+            swimmer.insert(InWater {});
+        }
+        let swimmer = swimmer.id();
+        (app, swimmer)
+    }
+
+    /// Advances time by `seconds`, runs `swim` and returns the force it applied
+    fn step(app: &mut App, swimmer: Entity, seconds: f32) -> Vec2 {
+        let mut time = app.world.resource_mut::<Time>();
+        let last_update = time.last_update().unwrap();
+        time.update_with_instant(last_update + Duration::from_secs_f32(seconds));
+        app.update();
+
+        let mut force = app.world.get_mut::<ExternalForce>(swimmer).unwrap();
+        let applied = force.force();
+        *force = ExternalForce::default();
+        applied
+    }
+
+    #[test]
+    fn stroke_only_pushes_during_power_phase() {
+        let (mut app, swimmer) = swim_app(true);
+
+        // The stroke starts and pushes for `power_phase` seconds
+        assert_eq!(step(&mut app, swimmer, 0.1), Vec2::X * SETTINGS.stroke_force);
+        assert_eq!(step(&mut app, swimmer, 0.1), Vec2::X * SETTINGS.stroke_force);
+        // Then glides until the stroke is over
+        assert_eq!(step(&mut app, swimmer, 0.2), Vec2::ZERO);
+        assert_eq!(step(&mut app, swimmer, 0.5), Vec2::ZERO);
+        // And the next one starts
+        assert_eq!(step(&mut app, swimmer, 0.3), Vec2::X * SETTINGS.stroke_force);
+    }
+
+    #[test]
+    fn limb_propelled_strokes_do_not_push() {
+        let (mut app, swimmer) = swim_app(true);
+        app.world.entity_mut(swimmer).insert(LimbPropelled);
+
+        assert_eq!(step(&mut app, swimmer, 0.1), Vec2::ZERO);
+        assert!(app.world.get::<Swimmer>(swimmer).unwrap().stroke_timer > 0.0);
+    }
+
+    #[test]
+    fn flails_weakly_out_of_water() {
+        let (mut app, swimmer) = swim_app(false);
+
+        for _ in 0..10 {
+            let force = step(&mut app, swimmer, 0.1);
+            assert_eq!(force, Vec2::X * SETTINGS.flail_force);
+            assert!(force.length() < SETTINGS.stroke_force);
+        }
+        assert_eq!(app.world.get::<Swimmer>(swimmer).unwrap().stroke_timer, 0.0);
+    }
+}
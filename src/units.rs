@@ -0,0 +1,110 @@
+//! Conversions between the units the game works in.
+//!
+//! - Grid: `GridCoords` of IntGrid cells, counted from the bottom left of their level.
+//! - Pixels: the LDtk world is authored in pixels, and world space *is* pixel space.
+//!   Transforms, `Position`s, collider shapes and sprites all use it.
+//!   Levels and their layers can be offset, see `world_transform`.
+//! - Meters: only for physical quantities that are easier to think about in the real world,
+//!   like gravity and impact speeds. `PIXELS_PER_METER` pixels make a meter.
+//!
+//! Masses come from collider areas in square pixels, so forces, torques and drag
+//! coefficients are tuned for pixels too, and are far larger than they would be in meters.
+//!
+//! Every spawn system goes through here, so the player, the walls and the water line up.
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::rect_merge::GridRect;
+
+pub const PIXELS_PER_METER: f32 = 8.0;
+pub const METERS_PER_PIXEL: f32 = 1.0 / PIXELS_PER_METER;
+
+/// Earth's, in meters per second squared
+pub const GRAVITY: f32 = 9.81;
+
+/// The center of a cell, in pixels from the bottom left of its level
+pub fn grid_to_pixel(coords: GridCoords, grid_size: i32) -> Vec2 {
+    (Vec2::new(coords.x as f32, coords.y as f32) + Vec2::splat(0.5)) * grid_size as f32
+}
+
+/// A corner between cells, in pixels from the bottom left of its level.
+/// Corner `(x, y)` is the bottom left corner of cell `(x, y)`.
+pub fn grid_corner_to_pixel(corner: IVec2, grid_size: i32) -> Vec2 {
+    corner.as_vec2() * grid_size as f32
+}
+
+/// The area covered by a rectangle of cells, in pixels from the bottom left of its level
+pub fn grid_rect_to_pixel(rect: GridRect, grid_size: i32) -> Rect {
+    let min = grid_corner_to_pixel(IVec2::new(rect.left, rect.bottom), grid_size);
+    let size = grid_corner_to_pixel(IVec2::new(rect.width(), rect.height()), grid_size);
+    Rect::from_corners(min, min + size)
+}
+
+pub fn meters_to_pixels(meters: f32) -> f32 {
+    meters * PIXELS_PER_METER
+}
+
+pub fn pixels_to_meters(pixels: f32) -> f32 {
+    pixels * METERS_PER_PIXEL
+}
+
+/// Gravity for the physics world, which runs in pixels
+pub fn gravity() -> Vec2 {
+    Vec2::NEG_Y * meters_to_pixels(GRAVITY)
+}
+
+/// Where an entity is in the world, composed from its own and all its ancestors' transforms.
+///
+/// Unlike `GlobalTransform`, this is already right on the frame the entity is spawned,
+/// before transforms are propagated.
+pub fn world_transform(entity: Entity, transform_query: &Query<(&Transform, Option<&Parent>)>) -> Transform {
+    let mut world = Transform::IDENTITY;
+    let mut current = Some(entity);
+    while let Some(entity) = current {
+        let Ok((transform, parent)) = transform_query.get(entity) else { break; };
+        world = transform.mul_transform(world);
+        current = parent.map(|parent| parent.get());
+    }
+    world
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+    use super::*;
+
+    const GRID_SIZE: i32 = 8;
+
+    #[test]
+    fn cell_centres_sit_between_their_corners() {
+        assert_eq!(grid_to_pixel(GridCoords::new(0, 0), GRID_SIZE), Vec2::new(4.0, 4.0));
+        assert_eq!(grid_corner_to_pixel(IVec2::new(0, 0), GRID_SIZE), Vec2::ZERO);
+
+        let coords = GridCoords::new(3, 32);
+        let bottom_left = grid_corner_to_pixel(IVec2::new(3, 32), GRID_SIZE);
+        let top_right = grid_corner_to_pixel(IVec2::new(4, 33), GRID_SIZE);
+        assert_eq!(bottom_left, Vec2::new(24.0, 256.0));
+        assert_eq!(grid_to_pixel(coords, GRID_SIZE), (bottom_left + top_right) / 2.0);
+    }
+
+    #[test]
+    fn gravity_is_earths_in_pixels() {
+        assert_eq!(gravity(), Vec2::NEG_Y * GRAVITY * PIXELS_PER_METER);
+        assert_eq!(pixels_to_meters(gravity().length()), GRAVITY);
+    }
+
+    #[test]
+    fn world_transform_composes_ancestors() {
+        let mut world = World::new();
+        let level = world.spawn(Transform::from_xyz(100.0, -50.0, 0.0)).id();
+        let layer = world.spawn(Transform::from_xyz(0.0, 8.0, 2.0)).set_parent(level).id();
+        let entity = world.spawn(Transform::from_xyz(4.0, 4.0, 0.0)).set_parent(layer).id();
+
+        let mut state: SystemState<Query<(&Transform, Option<&Parent>)>> = SystemState::new(&mut world);
+        let transform_query = state.get(&world);
+
+        assert_eq!(world_transform(level, &transform_query).translation, Vec3::new(100.0, -50.0, 0.0));
+        assert_eq!(world_transform(layer, &transform_query).translation, Vec3::new(100.0, -42.0, 2.0));
+        assert_eq!(world_transform(entity, &transform_query).translation, Vec3::new(104.0, -38.0, 2.0));
+    }
+}
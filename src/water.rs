@@ -0,0 +1,328 @@
+use bevy::ecs::query::Has;
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use std::collections::HashSet;
+use crate::fields::float_field;
+use crate::game_state::GameState;
+use crate::merged_collider::MergedRect;
+use crate::AirPocket;
+
+/// Physics of bodies in water.
+///
+/// How thick the water is comes from the `WaterBody` its sensors are registered with,
+/// which levels can override through float fields on the level in the LDtk project:
+/// `linear_drag`, `quadratic_drag` and `angular_damping`.
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WaterSettings>()
+            .add_event::<EnteredWater>()
+            .add_event::<ExitedWater>()
+            .add_systems(Update, (track_water_contacts, apply_level_water_settings))
+            .add_systems(Update, (buoyancy, drag)
+                .after(track_water_contacts)
+                .after(apply_level_water_settings)
+                .run_if(in_state(GameState::Playing)));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct WaterSettings {
+    /// Mass per square unit of water.
+    /// Bodies with a lower density than this float.
+    pub fluid_density: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            fluid_density: 1.5,
+        }
+    }
+}
+
+/// The merged sensors that make up bodies of water, and how thick the water in them is
+#[derive(Copy, Clone, Debug, Component)]
+pub struct WaterBody {
+    /// Drag proportional to velocity, dominates when moving slowly
+    pub linear_drag: f32,
+    /// Drag proportional to velocity squared, dominates when moving fast
+    pub quadratic_drag: f32,
+    /// Torque opposing spin, proportional to angular velocity
+    pub angular_damping: f32,
+}
+
+impl Default for WaterBody {
+    fn default() -> Self {
+        Self {
+            linear_drag: 51.2,
+            quadratic_drag: 0.4,
+            angular_damping: 2048.0,
+        }
+    }
+}
+
+/// Present while a body overlaps any body of water, air pockets included
+#[derive(Component)]
+pub struct InWater {}
+
+/// Present while a body overlaps an air pocket
+#[derive(Component)]
+pub struct InAirPocket {}
+
+/// The water sensors a body currently overlaps.
+///
+/// Bodies of water are made of many merged sensors,
+/// so `InWater` is only removed once the body has left all of them.
+#[derive(Clone, Debug, Default, Component)]
+pub struct WaterContacts {
+    pub sensors: HashSet<Entity>,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct EnteredWater {
+    pub entity: Entity,
+    /// Velocity of the body as it entered, in pixels per second
+    pub velocity: Vec2,
+}
+
+/// Also sent when the water is despawned around the body
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ExitedWater {
+    pub entity: Entity,
+}
+
+/// A ball shaped body that is pushed up by the water it displaces
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Buoyant {
+    pub radius: f32,
+}
+
+/// Keeps `WaterContacts` up to date from collisions with water sensors
+/// and derives `InWater` and `InAirPocket` from it
+pub fn track_water_contacts(
+    mut commands: Commands,
+    mut started: EventReader<CollisionStarted>,
+    mut ended: EventReader<CollisionEnded>,
+    water_query: Query<(), With<WaterBody>>,
+    air_pocket_query: Query<(), (With<WaterBody>, With<AirPocket>)>,
+    mut contacts_query: Query<(Entity, &mut WaterContacts, Option<&LinearVelocity>, Has<InAirPocket>)>,
+    mut entered_water: EventWriter<EnteredWater>,
+    mut exited_water: EventWriter<ExitedWater>,
+) {
+    let pairs = |entity1: Entity, entity2: Entity| [(entity1, entity2), (entity2, entity1)];
+
+    // Started before ended, so moving from one sensor to the next within a frame
+    // never leaves the body out of the water in between
+    for CollisionStarted(entity1, entity2) in started.iter() {
+        for (body, water) in pairs(*entity1, *entity2) {
+            if !water_query.contains(water) {
+                continue;
+            }
+            let Ok((entity, mut contacts, velocity, _)) = contacts_query.get_mut(body) else { continue; };
+            if contacts.sensors.is_empty() {
+                commands.entity(entity).insert(InWater {});
+                entered_water.send(EnteredWater {
+                    entity,
+                    velocity: velocity.map_or(Vec2::ZERO, |v| v.0),
+                });
+            }
+            contacts.sensors.insert(water);
+        }
+    }
+
+    for CollisionEnded(entity1, entity2) in ended.iter() {
+        for (body, water) in pairs(*entity1, *entity2) {
+            let Ok((entity, mut contacts, _, _)) = contacts_query.get_mut(body) else { continue; };
+            if contacts.sensors.remove(&water) && contacts.sensors.is_empty() {
+                commands.entity(entity).remove::<InWater>();
+                exited_water.send(ExitedWater { entity });
+            }
+        }
+    }
+
+    for (entity, mut contacts, _, in_air_pocket) in contacts_query.iter_mut() {
+        // Sensors despawned with their level never send CollisionEnded
+        if contacts.sensors.iter().any(|sensor| !water_query.contains(*sensor)) {
+            contacts.sensors.retain(|sensor| water_query.contains(*sensor));
+            if contacts.sensors.is_empty() {
+                commands.entity(entity).remove::<InWater>();
+                exited_water.send(ExitedWater { entity });
+            }
+        }
+
+        let touches_air_pocket = contacts.sensors.iter().any(|sensor| air_pocket_query.contains(*sensor));
+        if touches_air_pocket && !in_air_pocket {
+            commands.entity(entity).insert(InAirPocket {});
+        } else if !touches_air_pocket && in_air_pocket {
+            commands.entity(entity).remove::<InAirPocket>();
+        }
+    }
+}
+
+/// Applies the drag overrides of a level to its water as it spawns,
+/// keeping the registered values for anything the level doesn't set.
+///
+/// Merged sensors are children of their level.
+pub fn apply_level_water_settings(
+    mut water_query: Query<(&mut WaterBody, &Parent), Added<WaterBody>>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    levels: Res<Assets<LdtkLevel>>,
+) {
+    for (mut water, parent) in water_query.iter_mut() {
+        let Some(ldtk_level) = level_query
+            .get(parent.get())
+            .ok()
+            .and_then(|handle| levels.get(handle)) else { continue; };
+
+        let fields = &ldtk_level.level.field_instances;
+
+        *water = WaterBody {
+            linear_drag: float_field(fields, "linear_drag").unwrap_or(water.linear_drag),
+            quadratic_drag: float_field(fields, "quadratic_drag").unwrap_or(water.quadratic_drag),
+            angular_damping: float_field(fields, "angular_damping").unwrap_or(water.angular_damping),
+        };
+    }
+}
+
+/// Pushes bodies up by the weight of the water they displace,
+/// so they settle at the surface instead of bobbing around it.
+pub fn buoyancy(
+    settings: Res<WaterSettings>,
+    gravity: Res<Gravity>,
+    water_query: Query<(&Position, &MergedRect), With<WaterBody>>,
+    mut query: Query<(&mut ExternalForce, &Position, &Buoyant), With<InWater>>,
+) {
+    for (mut force, position, buoyant) in query.iter_mut() {
+        // Water rectangles never overlap, so their submerged areas simply add up
+        let displaced: f32 = water_query
+            .iter()
+            .map(|(water_position, rect)| {
+                submerged_area(position.0, buoyant.radius, Rect::from_center_size(water_position.0, rect.size))
+            })
+            .sum();
+
+        force.apply_force(-gravity.0 * settings.fluid_density * displaced);
+    }
+}
+
+/// Slows bodies down and stops them spinning in proportion to how much of them is under water.
+/// A body in several bodies of water gets the drag of each for the part of it inside that one.
+pub fn drag(
+    water_query: Query<(&Position, &MergedRect, &WaterBody)>,
+    mut query: Query<(
+        &mut ExternalForce,
+        &mut ExternalTorque,
+        &LinearVelocity,
+        &AngularVelocity,
+        &Position,
+        &Buoyant,
+    ), With<InWater>>,
+) {
+    for (mut force, mut torque, velocity, angular_velocity, position, buoyant) in query.iter_mut() {
+        let total_area = std::f32::consts::PI * buoyant.radius * buoyant.radius;
+        let speed = velocity.0.length();
+
+        for (water_position, rect, water) in water_query.iter() {
+            let submerged = submerged_area(
+                position.0,
+                buoyant.radius,
+                Rect::from_center_size(water_position.0, rect.size),
+            ) / total_area;
+            if submerged <= 0.0 {
+                continue;
+            }
+
+            force.apply_force(-velocity.0 * (water.linear_drag + water.quadratic_drag * speed) * submerged);
+            torque.apply_torque(-angular_velocity.0 * water.angular_damping * submerged);
+        }
+    }
+}
+
+/// The area of a circle that lies inside a rectangle,
+/// integrated over horizontal slices of the circle
+pub fn submerged_area(center: Vec2, radius: f32, water: Rect) -> f32 {
+    const SLICES: usize = 16;
+
+    let bottom = (center.y - radius).max(water.min.y);
+    let top = (center.y + radius).min(water.max.y);
+    if top <= bottom {
+        return 0.0;
+    }
+
+    let slice_height = (top - bottom) / SLICES as f32;
+    (0..SLICES)
+        .map(|i| {
+            let y = bottom + (i as f32 + 0.5) * slice_height;
+            let half_chord = (radius * radius - (y - center.y).powi(2)).max(0.0).sqrt();
+            let left = (center.x - half_chord).max(water.min.x);
+            let right = (center.x + half_chord).min(water.max.x);
+            (right - left).max(0.0) * slice_height
+        })
+        .sum()
+}
+
+/// How far below the surface of the water a point is, or `None` when it isn't in the water.
+///
+/// Bodies of water are made of stacked rectangles,
+/// so this climbs from the rectangle containing the point up to the top one.
+pub fn depth_below_surface(point: Vec2, water: &[Rect]) -> Option<f32> {
+    // Stacked rectangles share an edge, look just past it for the next one
+    const STEP: f32 = 0.01;
+
+    let mut surface = water.iter().find(|rect| rect.contains(point))?.max.y;
+    while let Some(above) = water.iter().find(|rect| rect.contains(Vec2::new(point.x, surface + STEP))) {
+        surface = above.max.y;
+    }
+
+    Some(surface - point.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance, "{actual} is not within {tolerance} of {expected}");
+    }
+
+    #[test]
+    fn submerged_area_of_a_circle() {
+        let water = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let full = PI * 4.0 * 4.0;
+
+        assert_close(submerged_area(Vec2::new(50.0, 50.0), 4.0, water), full, full * 0.01);
+        // Half in at the surface, or at a side
+        assert_close(submerged_area(Vec2::new(50.0, 100.0), 4.0, water), full / 2.0, full * 0.01);
+        assert_close(submerged_area(Vec2::new(0.0, 50.0), 4.0, water), full / 2.0, full * 0.01);
+        // Just touching, and well clear of the water
+        assert_eq!(submerged_area(Vec2::new(50.0, 104.0), 4.0, water), 0.0);
+        assert_eq!(submerged_area(Vec2::new(-20.0, 50.0), 4.0, water), 0.0);
+    }
+
+    #[test]
+    fn submerged_area_grows_with_depth() {
+        let water = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let areas: Vec<f32> = (0..=8)
+            .map(|i| submerged_area(Vec2::new(50.0, 104.0 - i as f32), 4.0, water))
+            .collect();
+        assert!(areas.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn depth_below_stacked_surfaces() {
+        // A wide pool with a narrow shaft of water on top of it
+        let water = [Rect::new(0.0, 0.0, 100.0, 50.0), Rect::new(40.0, 50.0, 48.0, 80.0)];
+
+        assert_eq!(depth_below_surface(Vec2::new(44.0, 10.0), &water), Some(70.0));
+        assert_eq!(depth_below_surface(Vec2::new(44.0, 70.0), &water), Some(10.0));
+        // Beside the shaft, the pool's own top is the surface
+        assert_eq!(depth_below_surface(Vec2::new(10.0, 10.0), &water), Some(40.0));
+        assert_eq!(depth_below_surface(Vec2::new(10.0, 60.0), &water), None);
+        assert_eq!(depth_below_surface(Vec2::new(44.0, 10.0), &[]), None);
+    }
+}
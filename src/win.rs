@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use bevy_xpbd_2d::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use crate::game_state::GameState;
+use crate::merged_collider::MergedColliderPlugin;
+use crate::sequence::AfterOutro;
+use crate::{player_contacts, Layer, Player};
+
+/// Handles reaching the `win` tile of a level.
+///
+/// Touching a win sensor sends `LevelCompleted`, which moves `LevelSelection`
+/// on to the next level of the project, or on to the outro and `GameState::Victory`
+/// when there are no levels left.
+pub struct WinPlugin;
+
+impl Plugin for WinPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<LevelCompleted>()
+            .register_ldtk_int_cell::<WinBundle>(5)
+            .add_plugins(
+                MergedColliderPlugin::<Win>::new(CollisionLayers::new([Layer::Win], [Layer::Player]))
+                    .sensor()
+                    .with_extra(WinSensor)
+            )
+            .add_systems(Update, (detect_level_completed, advance_level).chain().run_if(in_state(GameState::Playing)));
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Win;
+
+#[derive(Clone, Debug, Default, Bundle, LdtkIntCell)]
+pub struct WinBundle {
+    win: Win,
+}
+
+/// Marks the sensor spawned for win tiles
+#[derive(Copy, Clone, Debug, Default, Component)]
+pub struct WinSensor;
+
+#[derive(Event, Clone, Debug)]
+pub struct LevelCompleted {
+    pub level_iid: String,
+}
+
+pub fn detect_level_completed(
+    mut collision_event_reader: EventReader<CollisionStarted>,
+    player_query: Query<(), With<Player>>,
+    win_query: Query<&Parent, With<WinSensor>>,
+    level_query: Query<&Handle<LdtkLevel>>,
+    levels: Res<Assets<LdtkLevel>>,
+    mut level_completed: EventWriter<LevelCompleted>,
+) {
+    for (_, other) in player_contacts(collision_event_reader.iter(), &player_query) {
+        // Win sensors are spawned as children of the level they complete
+        let Ok(parent) = win_query.get(other) else { continue; };
+        let Some(level) = level_query
+            .get(parent.get())
+            .ok()
+            .and_then(|handle| levels.get(handle)) else { continue; };
+
+        level_completed.send(LevelCompleted {
+            level_iid: level.level.iid.clone(),
+        });
+    }
+}
+
+/// Moves on to the level after the completed one, in project order.
+///
+/// The player is despawned so that a fresh one is spawned
+/// at the player start of the next level.
+pub fn advance_level(
+    mut commands: Commands,
+    mut level_completed: EventReader<LevelCompleted>,
+    project_query: Query<&Handle<LdtkAsset>>,
+    projects: Res<Assets<LdtkAsset>>,
+    player_query: Query<Entity, With<Player>>,
+    mut level_selection: ResMut<LevelSelection>,
+    mut after_outro: ResMut<AfterOutro>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    // Touching several win sensors in one frame still only completes the level once
+    let Some(completed) = level_completed.iter().last() else { return; };
+    let Ok(project_handle) = project_query.get_single() else { return; };
+    let Some(project) = projects.get(project_handle) else { return; };
+
+    for player in player_query.iter() {
+        commands.entity(player).despawn_recursive();
+    }
+
+    let levels = &project.project.levels;
+    let next_level = levels
+        .iter()
+        .position(|level| level.iid == completed.level_iid)
+        .and_then(|index| levels.get(index + 1));
+
+    match next_level {
+        Some(level) => *level_selection = LevelSelection::Iid(level.iid.clone()),
+        None => {
+            *after_outro = AfterOutro(GameState::Victory);
+            next_state.set(GameState::Outro);
+        }
+    }
+}